@@ -0,0 +1,10 @@
+-- This file is used to create the table idempotency_keys in the database.
+CREATE TABLE idempotency_keys (
+  key TEXT PRIMARY KEY NOT NULL,
+  fingerprint TEXT NOT NULL,
+  status INTEGER NOT NULL,
+  body TEXT NOT NULL,
+  created_at BIGINT NOT NULL
+);
+
+CREATE INDEX idempotency_keys_created_at ON idempotency_keys (created_at);
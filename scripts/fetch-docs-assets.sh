@@ -0,0 +1,31 @@
+#!/usr/bin/env bash
+
+# switch to parent directory
+script_path=`dirname ${BASH_SOURCE[0]}`
+pushd $script_path/..
+
+# Keep in sync with SWAGGER_UI_VERSION and REDOC_VERSION in src/docs_assets.rs.
+swagger_ui_version="5.17.14"
+redoc_version="2.0.0-rc.72"
+
+assets_dir="assets/docs"
+download_dir=`mktemp -d`
+trap "rm -rf $download_dir" EXIT
+
+echo "Fetch Swagger UI $swagger_ui_version ..."
+for file in swagger-ui.css swagger-ui-bundle.js; do
+    curl --fail --silent --show-error --location --output $download_dir/$file \
+        "https://unpkg.com/swagger-ui-dist@$swagger_ui_version/$file" || exit 1
+done
+
+echo "Fetch Redoc $redoc_version ..."
+curl --fail --silent --show-error --location --output $download_dir/redoc.standalone.js \
+    "https://cdn.redoc.ly/redoc/v$redoc_version/bundles/redoc.standalone.js" || exit 1
+
+# Only files matching the recorded checksums replace the vendored copies.
+echo "Verify against $assets_dir/SHA256SUMS ..."
+cp $assets_dir/SHA256SUMS $download_dir/
+(cd $download_dir && sha256sum --check SHA256SUMS) || exit 1
+cp $download_dir/swagger-ui.css $download_dir/swagger-ui-bundle.js $download_dir/redoc.standalone.js $assets_dir/
+
+popd
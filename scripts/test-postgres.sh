@@ -0,0 +1,27 @@
+#!/usr/bin/env bash
+
+# switch to parent directory
+script_path=`dirname ${BASH_SOURCE[0]}`
+pushd $script_path/..
+
+# Use the server in TEST_DATABASE_URL if set, otherwise start a throwaway cluster.
+if [ -z "$TEST_DATABASE_URL" ]; then
+    port=55432
+    data_dir=`mktemp -d`
+
+    echo "Start temporary Postgres in $data_dir ..."
+    initdb --pgdata $data_dir --auth trust --username postgres > /dev/null
+    pg_ctl --pgdata $data_dir --options "-p $port -k $data_dir" --log $data_dir/postgres.log --wait start
+
+    trap "pg_ctl --pgdata $data_dir --mode immediate stop; rm -rf $data_dir" EXIT
+
+    export TEST_DATABASE_URL="postgres://postgres@localhost:$port/postgres"
+fi
+
+echo "Run tests against Postgres ..."
+cargo test --features postgres "$@"
+status=$?
+
+popd
+
+exit $status
@@ -0,0 +1,377 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use time::format_description::FormatItem;
+use time::macros::format_description;
+use time::OffsetDateTime;
+use warp::http::header::{self, HeaderMap, HeaderValue};
+use warp::http::{Method, StatusCode};
+use warp::hyper::body::HttpBody;
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::{AccessLogConfig, AccessLogFormat, Rotation};
+
+/// `%t` in Apache's log formats, e.g. `10/Oct/2000:13:55:36 +0000`.
+const CLF_TIME: &[FormatItem<'static>] = format_description!(
+    "[day]/[month repr:short]/[year]:[hour]:[minute]:[second] [offset_hour sign:mandatory][offset_minute]"
+);
+
+/// Suffix of rotated files, e.g. `access.log.20001010T135536`.
+const ROTATED_SUFFIX: &[FormatItem<'static>] =
+    format_description!("[year][month][day]T[hour][minute][second]");
+
+/// Writes one line per response in Common or Combined Log Format.
+///
+/// Lines are handed to a dedicated thread that owns the file, so writes and
+/// rotations never block the async runtime.
+pub struct AccessLog {
+    format: AccessLogFormat,
+    sender: mpsc::Sender<Message>,
+}
+
+enum Message {
+    Line(String),
+    /// Acknowledged once every earlier line is written.
+    Flush(mpsc::SyncSender<()>),
+}
+
+/// A request as it appears in the access log.
+pub struct Entry<'a> {
+    pub remote_addr: Option<SocketAddr>,
+    pub received: OffsetDateTime,
+    pub method: &'a Method,
+    /// Path and query, as requested.
+    pub target: &'a str,
+    pub status: StatusCode,
+    /// Response body size, if known.
+    pub bytes: Option<u64>,
+    /// Raw header bytes, which need not be UTF-8.
+    pub referer: Option<&'a [u8]>,
+    pub user_agent: Option<&'a [u8]>,
+}
+
+impl AccessLog {
+    pub fn open(path: &Path, config: &AccessLogConfig) -> io::Result<AccessLog> {
+        let mut file = RotatingFile::open(
+            path,
+            config.rotation,
+            config.max_size_bytes,
+            config.max_files,
+        )?;
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || {
+                for message in receiver {
+                    match message {
+                        Message::Line(line) => {
+                            if let Err(e) = file.write_line(&line, OffsetDateTime::now_utc()) {
+                                tracing::warn!(error = %e, path = %file.path.display(), "failed to write access log");
+                            }
+                        }
+                        Message::Flush(done) => {
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            })?;
+        Ok(AccessLog {
+            format: config.format,
+            sender,
+        })
+    }
+
+    pub fn write(&self, entry: &Entry) {
+        self.send(Message::Line(entry.format(self.format)));
+    }
+
+    /// Blocks until every line written so far is on disk.
+    pub fn flush(&self) {
+        let (done, flushed) = mpsc::sync_channel(1);
+        self.send(Message::Flush(done));
+        let _ = flushed.recv();
+    }
+
+    fn send(&self, message: Message) {
+        // The writer thread only stops if it panicked, which it has already reported.
+        let _ = self.sender.send(message);
+    }
+}
+
+impl Entry<'_> {
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        let host = self
+            .remote_addr
+            .map_or_else(|| "-".to_string(), |addr| addr.ip().to_string());
+        let received = self.received.format(CLF_TIME).expect("CLF time formats");
+        let bytes = self
+            .bytes
+            .filter(|&bytes| bytes > 0)
+            .map_or_else(|| "-".to_string(), |bytes| bytes.to_string());
+        // warp does not expose the request's HTTP version to filters; plain-text
+        // connections are HTTP/1.1 unless a client insists on HTTP/2.
+        let mut line = format!(
+            "{} - - [{}] \"{}\" {} {}",
+            host,
+            received,
+            escape(format!("{} {} HTTP/1.1", self.method, self.target).as_bytes()),
+            self.status.as_u16(),
+            bytes,
+        );
+        if format == AccessLogFormat::Combined {
+            line.push_str(&format!(
+                " \"{}\" \"{}\"",
+                escape(self.referer.unwrap_or(b"-")),
+                escape(self.user_agent.unwrap_or(b"-")),
+            ));
+        }
+        line
+    }
+}
+
+/// Escapes a quoted field the way Apache does, so a line always parses.
+fn escape(value: &[u8]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for &byte in value {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    escaped
+}
+
+/// A log file that is renamed with a timestamp suffix and replaced once it
+/// reaches `max_size` bytes or a new `rotation` period starts, keeping the
+/// newest `max_files` rotated files.
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+    period: i64,
+}
+
+impl RotatingFile {
+    pub fn open(
+        path: &Path,
+        rotation: Rotation,
+        max_size: u64,
+        max_files: usize,
+    ) -> io::Result<RotatingFile> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = open_append(path)?;
+        let metadata = file.metadata()?;
+        // An existing file belongs to the period it was last written in.
+        let modified = metadata
+            .modified()
+            .map(OffsetDateTime::from)
+            .unwrap_or_else(|_| OffsetDateTime::now_utc());
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            rotation,
+            max_size,
+            max_files,
+            file,
+            size: metadata.len(),
+            period: period(rotation, modified),
+        })
+    }
+
+    pub fn write_line(&mut self, line: &str, now: OffsetDateTime) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        let period = period(self.rotation, now);
+        let too_big = self.max_size > 0 && self.size + len > self.max_size;
+        if self.size > 0 && (period != self.period || too_big) {
+            self.rotate(now)?;
+        }
+        self.period = period;
+
+        self.file.write_all(format!("{}\n", line).as_bytes())?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self, now: OffsetDateTime) -> io::Result<()> {
+        let stamp = now.format(ROTATED_SUFFIX).expect("timestamps format");
+        let mut rotated = self.sibling(&stamp);
+        let mut n = 1;
+        while rotated.exists() {
+            rotated = self.sibling(&format!("{}-{}", stamp, n));
+            n += 1;
+        }
+        fs::rename(&self.path, &rotated)?;
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        self.prune()
+    }
+
+    /// Deletes the oldest rotated files beyond `max_files`.
+    fn prune(&self) -> io::Result<()> {
+        if self.max_files == 0 {
+            return Ok(());
+        }
+        let mut rotated = self.rotated_files()?;
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.max_files);
+        for path in &rotated[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Rotated files, whose names sort oldest first.
+    pub fn rotated_files(&self) -> io::Result<Vec<PathBuf>> {
+        let prefix = format!("{}.", self.file_name());
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut rotated = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let is_rotated = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .is_some_and(is_rotated_suffix);
+            if is_rotated {
+                rotated.push(entry.path());
+            }
+        }
+        Ok(rotated)
+    }
+
+    fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .expect("access log path names a file")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        self.path
+            .with_file_name(format!("{}.{}", self.file_name(), suffix))
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Matches suffixes written by [`RotatingFile::rotate`], so unrelated files are never pruned.
+fn is_rotated_suffix(suffix: &str) -> bool {
+    let (stamp, counter) = suffix.split_once('-').unwrap_or((suffix, "0"));
+    stamp.len() == 15
+        && stamp.bytes().enumerate().all(|(i, b)| {
+            if i == 8 {
+                b == b'T'
+            } else {
+                b.is_ascii_digit()
+            }
+        })
+        && !counter.is_empty()
+        && counter.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Numbers the UTC hour or day containing `time`, so a change means a new file is due.
+fn period(rotation: Rotation, time: OffsetDateTime) -> i64 {
+    let secs = time.unix_timestamp();
+    match rotation {
+        Rotation::Never => 0,
+        Rotation::Hourly => secs.div_euclid(60 * 60),
+        Rotation::Daily => secs.div_euclid(24 * 60 * 60),
+    }
+}
+
+/// Writes an access log entry for every response of `filter`; a `None` log
+/// passes responses through untouched.
+pub fn wrap<F, T>(
+    log: Option<Arc<AccessLog>>,
+    filter: F,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (T,), Error = Rejection> + Clone + Send + Sync + 'static,
+    T: Reply,
+{
+    let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+    // The headers the log needs are copied only while it is on.
+    let enabled = log.is_some();
+    let headers = warp::any()
+        .and_then(move || async move {
+            if enabled {
+                Ok(())
+            } else {
+                Err(warp::reject())
+            }
+        })
+        .untuple_one()
+        .and(warp::header::headers_cloned())
+        .map(Some)
+        .or(warp::any().map(|| None))
+        .unify();
+
+    warp::any()
+        .map(OffsetDateTime::now_utc)
+        .and(warp::addr::remote())
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(query)
+        .and(headers)
+        .and(filter)
+        .map(
+            move |received: OffsetDateTime,
+                  remote_addr: Option<SocketAddr>,
+                  method: Method,
+                  path: FullPath,
+                  query: String,
+                  headers: Option<HeaderMap>,
+                  reply: T| {
+                let response = reply.into_response();
+                let (Some(log), Some(headers)) = (&log, headers) else {
+                    return response;
+                };
+
+                let target = if query.is_empty() {
+                    path.as_str().to_string()
+                } else {
+                    format!("{}?{}", path.as_str(), query)
+                };
+                log.write(&Entry {
+                    remote_addr,
+                    received,
+                    method: &method,
+                    target: &target,
+                    status: response.status(),
+                    bytes: body_size(&response),
+                    referer: headers.get(header::REFERER).map(HeaderValue::as_bytes),
+                    user_agent: headers.get(header::USER_AGENT).map(HeaderValue::as_bytes),
+                });
+                response
+            },
+        )
+}
+
+fn body_size(response: &Response) -> Option<u64> {
+    response.body().size_hint().exact().or_else(|| {
+        response
+            .headers()
+            .get(header::CONTENT_LENGTH)?
+            .to_str()
+            .ok()?
+            .parse()
+            .ok()
+    })
+}
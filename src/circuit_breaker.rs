@@ -0,0 +1,68 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(10);
+
+/// Fails fast while a dependency keeps failing.
+///
+/// After `failure_threshold` consecutive failures the breaker opens and
+/// refuses calls for `cooldown`. Once the cooldown has elapsed a single
+/// trial call is let through: success closes the breaker, failure opens it
+/// for another cooldown.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            failure_threshold,
+            cooldown,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Returns whether a call may proceed, or else how much of the cooldown
+    /// remains.
+    pub fn allow(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        match state.opened_at {
+            None => Ok(()),
+            Some(opened_at) if opened_at.elapsed() >= self.cooldown => {
+                // Restart the cooldown so only this caller gets the trial call.
+                state.opened_at = Some(Instant::now());
+                Ok(())
+            }
+            Some(opened_at) => Err(self.cooldown - opened_at.elapsed()),
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures = 0;
+        state.opened_at = None;
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        if state.consecutive_failures >= self.failure_threshold {
+            state.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_COOLDOWN)
+    }
+}
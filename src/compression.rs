@@ -0,0 +1,319 @@
+use std::borrow::Cow;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use warp::http::header::{self, HeaderValue};
+use warp::http::StatusCode;
+use warp::hyper::body::HttpBody;
+use warp::hyper::Body;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::CompressionConfig;
+use crate::errors::Error;
+use crate::formats::media_range;
+
+/// Brotli quality and window size: quick enough for dynamic responses.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW_BITS: u32 = 22;
+
+/// A `Content-Encoding` the server can produce, in its order of preference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Coding {
+    const ALL: [Coding; 3] = [Coding::Brotli, Coding::Gzip, Coding::Deflate];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Coding::Brotli => "br",
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate",
+        }
+    }
+
+    /// Picks the coding an `Accept-Encoding` header weighs highest, or
+    /// `None` to send the body as is.
+    pub fn from_accept_encoding(accept_encoding: &str) -> Option<Coding> {
+        let entries = accept_encoding
+            .split(',')
+            .filter_map(media_range)
+            .map(|(coding, q)| match coding.as_str() {
+                "x-gzip" => ("gzip".to_string(), q),
+                _ => (coding, q),
+            })
+            .collect::<Vec<_>>();
+        let weight = |name: &str| {
+            entries
+                .iter()
+                .find(|(coding, _)| coding == name)
+                .or_else(|| entries.iter().find(|(coding, _)| coding == "*"))
+                .map_or(0.0, |(_, q)| *q)
+        };
+
+        let mut best = None;
+        for coding in Coding::ALL {
+            let weight = weight(coding.name());
+            if weight > 0.0 && best.is_none_or(|(_, best)| weight > best) {
+                best = Some((coding, weight));
+            }
+        }
+        best.map(|(coding, _)| coding)
+    }
+}
+
+/// One encoder per response, so every chunk is compressed against the ones
+/// before it.
+enum Encoder {
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(coding: Coding) -> Encoder {
+        match coding {
+            Coding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW_BITS,
+            ))),
+            Coding::Gzip => {
+                Encoder::Gzip(GzEncoder::new(Vec::new(), flate2::Compression::default()))
+            }
+            Coding::Deflate => {
+                Encoder::Deflate(ZlibEncoder::new(Vec::new(), flate2::Compression::default()))
+            }
+        }
+    }
+
+    /// Feeds `data` in, returning whatever compressed output is ready.
+    fn write(&mut self, data: &[u8]) -> Vec<u8> {
+        let written = match self {
+            Encoder::Brotli(encoder) => encoder.write_all(data),
+            Encoder::Gzip(encoder) => encoder.write_all(data),
+            Encoder::Deflate(encoder) => encoder.write_all(data),
+        };
+        written.expect("writing to a Vec cannot fail");
+        self.take_output()
+    }
+
+    /// Returns everything written so far in compressed form, without ending
+    /// the stream.
+    fn flush(&mut self) -> Vec<u8> {
+        let flushed = match self {
+            Encoder::Brotli(encoder) => encoder.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Deflate(encoder) => encoder.flush(),
+        };
+        flushed.expect("writing to a Vec cannot fail");
+        self.take_output()
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+        .expect("writing to a Vec cannot fail")
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(match self {
+            Encoder::Brotli(encoder) => encoder.get_mut(),
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+            Encoder::Deflate(encoder) => encoder.get_mut(),
+        })
+    }
+}
+
+/// Response compression settings, checked once at startup.
+pub struct Compression {
+    min_size: u64,
+    content_types: Vec<String>,
+}
+
+impl Compression {
+    pub fn new(config: &CompressionConfig) -> Self {
+        Compression {
+            min_size: config.min_size_bytes,
+            content_types: config
+                .content_types
+                .iter()
+                .map(|content_type| content_type.trim().to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    fn allows(&self, content_type: &str) -> bool {
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        self.content_types.iter().any(|allowed| {
+            *allowed == essence
+                || allowed.strip_suffix("/*").is_some_and(|kind| {
+                    essence
+                        .strip_prefix(kind)
+                        .is_some_and(|rest| rest.starts_with('/'))
+                })
+        })
+    }
+
+    /// Compresses `response` in the coding `accept_encoding` prefers, if its
+    /// type is allowed and it is not too small.
+    ///
+    /// Bodies of known length are compressed whole and keep a
+    /// `Content-Length`; streamed ones are compressed chunk by chunk.
+    async fn apply(&self, accept_encoding: Option<&str>, mut response: Response) -> Response {
+        let headers = response.headers();
+        let compressible = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| self.allows(content_type));
+        if !compressible || headers.contains_key(header::CONTENT_ENCODING) {
+            return response;
+        }
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+        let Some(coding) = accept_encoding.and_then(Coding::from_accept_encoding) else {
+            return response;
+        };
+        if matches!(
+            response.status(),
+            StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED
+        ) {
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+        let mut encoder = Encoder::new(coding);
+        let body = match body.size_hint().exact() {
+            Some(size) if size < self.min_size => return Response::from_parts(parts, body),
+            Some(_) => {
+                // Bodies of known length are already in memory.
+                let bytes = warp::hyper::body::to_bytes(body).await.unwrap_or_default();
+                let mut compressed = encoder.write(&bytes);
+                compressed.extend(encoder.finish());
+                parts
+                    .headers
+                    .insert(header::CONTENT_LENGTH, HeaderValue::from(compressed.len()));
+                Body::from(compressed)
+            }
+            None => stream(body, encoder),
+        };
+        parts.headers.insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(coding.name()),
+        );
+        Response::from_parts(parts, body)
+    }
+}
+
+/// Compresses a streamed body as the chunks arrive. Output is flushed only
+/// when the next chunk is not ready yet, so small chunks cost no more than
+/// one large one.
+fn stream(mut body: Body, mut encoder: Encoder) -> Body {
+    let (mut sender, compressed) = Body::channel();
+    tokio::spawn(async move {
+        let mut unflushed = false;
+        loop {
+            let ready = tokio::select! {
+                biased;
+                next = body.data() => Some(next),
+                () = std::future::ready(()) => None,
+            };
+            let next = match ready {
+                Some(next) => next,
+                None if unflushed => {
+                    // Fails once the client has gone away.
+                    if sender.send_data(encoder.flush().into()).await.is_err() {
+                        return;
+                    }
+                    body.data().await
+                }
+                None => body.data().await,
+            };
+            let chunk = match next {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(_)) => {
+                    sender.abort();
+                    return;
+                }
+                None => break,
+            };
+            let bytes = encoder.write(&chunk);
+            unflushed = true;
+            if !bytes.is_empty() && sender.send_data(bytes.into()).await.is_err() {
+                return;
+            }
+        }
+        let _ = sender.send_data(encoder.finish().into()).await;
+    });
+    compressed
+}
+
+/// Compresses the responses of `filter` per `compression`; `None` disables it.
+pub fn wrap<F, T>(
+    compression: Option<Arc<Compression>>,
+    filter: F,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (T,), Error = Rejection> + Clone + Send + Sync + 'static,
+    T: Reply,
+{
+    warp::header::optional::<String>("accept-encoding")
+        .and(filter)
+        .then(move |accept_encoding: Option<String>, reply: T| {
+            let compression = compression.clone();
+            async move {
+                let response = reply.into_response();
+                match compression {
+                    Some(compression) => {
+                        compression
+                            .apply(accept_encoding.as_deref(), response)
+                            .await
+                    }
+                    None => response,
+                }
+            }
+        })
+}
+
+/// Undoes the `Content-Encoding` of a request body, which may inflate to at
+/// most `max_bytes`. Only gzip is accepted; other codings are
+/// `415 Unsupported Media Type`.
+pub fn decode_request<'a>(
+    content_encoding: Option<&str>,
+    body: &'a [u8],
+    max_bytes: u64,
+) -> Result<Cow<'a, [u8]>, Error> {
+    let coding = content_encoding.map(|coding| coding.trim().to_ascii_lowercase());
+    match coding.as_deref() {
+        None | Some("" | "identity") => Ok(Cow::Borrowed(body)),
+        Some("gzip" | "x-gzip") => {
+            let mut inflated = Vec::new();
+            // One byte past the limit is enough to know it is exceeded.
+            MultiGzDecoder::new(body)
+                .take(max_bytes.saturating_add(1))
+                .read_to_end(&mut inflated)
+                .map_err(|_| Error::InvalidData)?;
+            if inflated.len() as u64 > max_bytes {
+                return Err(Error::PayloadTooLarge);
+            }
+            Ok(Cow::Owned(inflated))
+        }
+        Some(_) => Err(Error::UnsupportedMediaType),
+    }
+}
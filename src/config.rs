@@ -0,0 +1,769 @@
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::cors::Cors;
+use crate::db;
+use crate::shutdown;
+
+/// Config file read when neither `--config` nor `CONFIG_FILE` names one.
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+pub const DEFAULT_MAX_BODY_BYTES: u64 = 64 * 1024;
+
+pub const DEFAULT_MAX_IMPORT_BYTES: u64 = 16 * 1024 * 1024;
+
+pub const DEFAULT_ACCESS_LOG_MAX_SIZE_BYTES: u64 = 100 * 1024 * 1024;
+
+pub const DEFAULT_COMPRESSION_MIN_SIZE_BYTES: u64 = 1024;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read config file {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse config file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("invalid value for {name}={value:?}: {message}")]
+    Env {
+        name: &'static str,
+        value: String,
+        message: String,
+    },
+    #[error("invalid configuration:\n  - {}", .0.join("\n  - "))]
+    Invalid(Vec<String>),
+}
+
+/// Command-line flags. Any flag given overrides the config file and environment.
+#[derive(Parser, Debug, Default)]
+#[command(version, about = "Book Management API")]
+pub struct Cli {
+    /// TOML config file [env: CONFIG_FILE] [default: config.toml if present]
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on [env: BIND_ADDRESS]
+    #[arg(long)]
+    pub bind_address: Option<IpAddr>,
+    /// Port to listen on [env: PORT]
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Database URL [env: DATABASE_URL]
+    #[arg(long)]
+    pub database_url: Option<String>,
+    /// Maximum number of pooled connections [env: DB_POOL_MAX_SIZE]
+    #[arg(long)]
+    pub pool_max_size: Option<u32>,
+    /// Comma-separated allowed CORS origins [env: CORS_ALLOWED_ORIGINS]
+    #[arg(long, value_delimiter = ',')]
+    pub cors_allowed_origins: Option<Vec<String>>,
+    /// Log level [env: LOG_LEVEL]
+    #[arg(long)]
+    pub log_level: Option<LogLevel>,
+    /// Log output format [env: LOG_FORMAT]
+    #[arg(long)]
+    pub log_format: Option<LogFormat>,
+    /// Access log file [env: ACCESS_LOG_PATH]
+    #[arg(long, value_name = "FILE")]
+    pub access_log: Option<PathBuf>,
+    /// Largest accepted request body in bytes [env: MAX_BODY_BYTES]
+    #[arg(long)]
+    pub max_body_bytes: Option<u64>,
+    /// Where books are stored [env: BOOK_STORE]
+    #[arg(long)]
+    pub book_store: Option<BookStore>,
+}
+
+/// Application settings, layered from defaults, a TOML file, the environment
+/// and command-line flags, each overriding the one before.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub cors: CorsConfig,
+    pub log: LogConfig,
+    pub access_log: AccessLogConfig,
+    pub telemetry: TelemetryConfig,
+    pub limits: LimitsConfig,
+    pub compression: CompressionConfig,
+    pub features: FeatureConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    /// How long the listener stays open after a shutdown signal, while
+    /// `/readyz` already fails.
+    #[serde(rename = "pre_stop_delay_secs", with = "secs")]
+    pub pre_stop_delay: Duration,
+    /// How long in-flight requests may run once the listener has closed.
+    #[serde(rename = "drain_timeout_secs", with = "secs")]
+    pub drain_timeout: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 8001,
+            pre_stop_delay: shutdown::DEFAULT_PRE_STOP_DELAY,
+            drain_timeout: shutdown::DEFAULT_DRAIN_TIMEOUT,
+        }
+    }
+}
+
+/// `url` picks the backend, and with it which connection section applies.
+/// Sections for backends this binary was built without are ignored.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    /// Required; `:memory:` asks for an in-memory SQLite database.
+    pub url: String,
+    pub pool: db::PoolOptions,
+    #[cfg(feature = "sqlite")]
+    pub sqlite: db::SqliteOptions,
+    #[cfg(feature = "postgres")]
+    pub postgres: db::PostgresOptions,
+}
+
+impl DatabaseConfig {
+    pub fn connection(&self) -> db::ConnectionOptions {
+        db::ConnectionOptions {
+            #[cfg(feature = "sqlite")]
+            sqlite: self.sqlite.clone(),
+            #[cfg(feature = "postgres")]
+            postgres: self.postgres.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Origins allowed to call the API, e.g. `https://*.example.com`; `*` allows any origin.
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed in preflights; `*` allows any.
+    pub allowed_headers: Vec<String>,
+    /// Response headers scripts may read beyond the CORS-safelisted ones.
+    pub exposed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    /// How long browsers may cache a preflight; `0` in config omits the header.
+    #[serde(rename = "max_age_secs", with = "optional_secs")]
+    pub max_age: Option<Duration>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: ["GET", "POST", "PUT", "DELETE"].map(String::from).to_vec(),
+            allowed_headers: ["content-type", "content-encoding", "idempotency-key"]
+                .map(String::from)
+                .to_vec(),
+            exposed_headers: [
+                "idempotent-replayed",
+                "retry-after",
+                "location",
+                "deprecation",
+                "sunset",
+                "link",
+            ]
+            .map(String::from)
+            .to_vec(),
+            allow_credentials: false,
+            max_age: Some(Duration::from_secs(10 * 60)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: LogLevel,
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable, multi-line output for development.
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <LogFormat as ValueEnum>::from_str(s, true)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <LogLevel as ValueEnum>::from_str(s, true)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessLogConfig {
+    /// File to write the access log to; unset disables it.
+    pub path: Option<PathBuf>,
+    pub format: AccessLogFormat,
+    pub rotation: Rotation,
+    /// Starts a new file before the current one grows past this; `0` disables size-based rotation.
+    pub max_size_bytes: u64,
+    /// Rotated files to keep, deleting the oldest; `0` keeps them all.
+    pub max_files: usize,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        AccessLogConfig {
+            path: None,
+            format: AccessLogFormat::default(),
+            rotation: Rotation::default(),
+            max_size_bytes: DEFAULT_ACCESS_LOG_MAX_SIZE_BYTES,
+            max_files: 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// Common Log Format.
+    Common,
+    /// Common Log Format plus the referer and user agent, as Apache's `combined`.
+    #[default]
+    Combined,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <AccessLogFormat as ValueEnum>::from_str(s, true)
+    }
+}
+
+/// When to start a new access log file regardless of its size, in UTC.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Never,
+    Hourly,
+    #[default]
+    Daily,
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Rotation as ValueEnum>::from_str(s, true)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// OTLP collector to export traces to, e.g. `http://localhost:4317`; unset disables export.
+    pub otlp_endpoint: Option<String>,
+    pub otlp_protocol: OtlpProtocol,
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            otlp_endpoint: None,
+            otlp_protocol: OtlpProtocol::default(),
+            service_name: env!("CARGO_PKG_NAME").to_string(),
+        }
+    }
+}
+
+/// Transport for OTLP export, named as in `OTEL_EXPORTER_OTLP_PROTOCOL`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum OtlpProtocol {
+    #[default]
+    #[serde(rename = "grpc")]
+    Grpc,
+    #[serde(rename = "http/protobuf")]
+    HttpProtobuf,
+}
+
+impl FromStr for OtlpProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "grpc" => Ok(OtlpProtocol::Grpc),
+            "http/protobuf" => Ok(OtlpProtocol::HttpProtobuf),
+            _ => Err("expected \"grpc\" or \"http/protobuf\"".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_body_bytes: u64,
+    /// Largest body `POST /books/import` accepts, in place of `max_body_bytes`.
+    pub max_import_bytes: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            max_import_bytes: DEFAULT_MAX_IMPORT_BYTES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    /// Compresses responses with gzip, Brotli or deflate, per `Accept-Encoding`.
+    pub enabled: bool,
+    /// Smaller responses are sent as is; streamed ones are always compressed.
+    pub min_size_bytes: u64,
+    /// Media types worth compressing, e.g. `application/json` or `text/*`.
+    pub content_types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: true,
+            min_size_bytes: DEFAULT_COMPRESSION_MIN_SIZE_BYTES,
+            content_types: [
+                "application/json",
+                "application/x-ndjson",
+                "application/xml",
+                "application/yaml",
+                "application/javascript",
+                "text/*",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeatureConfig {
+    pub book_store: BookStore,
+    /// Serves the OpenAPI document, Swagger UI and Redoc.
+    pub docs: bool,
+    pub docs_assets: DocsAssets,
+    /// Serves `/diagnostics/db`.
+    pub diagnostics: bool,
+    /// Serves `/metrics` for Prometheus.
+    pub metrics: bool,
+}
+
+impl Default for FeatureConfig {
+    fn default() -> Self {
+        FeatureConfig {
+            book_store: BookStore::default(),
+            docs: true,
+            docs_assets: DocsAssets::default(),
+            diagnostics: true,
+            metrics: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BookStore {
+    /// Books live in the configured database.
+    #[default]
+    Database,
+    /// Books live in memory and are lost on restart.
+    Memory,
+}
+
+impl FromStr for BookStore {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <BookStore as ValueEnum>::from_str(s, true)
+    }
+}
+
+/// Where the Swagger UI and Redoc pages load their scripts and styles from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DocsAssets {
+    /// Swagger UI and Redoc are embedded in the binary and served under `/docs/assets/`.
+    #[default]
+    Bundled,
+    /// Loaded from unpkg, cdn.redoc.ly and Google Fonts.
+    Cdn,
+}
+
+impl FromStr for DocsAssets {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <DocsAssets as ValueEnum>::from_str(s, true)
+    }
+}
+
+impl Config {
+    /// Loads the config file, then applies `env` and `cli` overrides and validates the result.
+    pub fn load(cli: &Cli, env: impl Fn(&str) -> Option<String>) -> Result<Config, ConfigError> {
+        let mut config = match cli
+            .config
+            .clone()
+            .or_else(|| env("CONFIG_FILE").map(PathBuf::from))
+        {
+            Some(path) => Config::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Config::default(),
+        };
+
+        config.apply_env(&env)?;
+        config.apply_cli(cli);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        toml::from_str(&contents).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    fn apply_env(&mut self, env: &impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        override_from_env(&mut self.server.bind_address, env, "BIND_ADDRESS")?;
+        override_from_env(&mut self.server.port, env, "PORT")?;
+        if let Some(secs) = parse_env(env, "PRE_STOP_DELAY_SECS")? {
+            self.server.pre_stop_delay = Duration::from_secs(secs);
+        }
+        if let Some(secs) = parse_env(env, "DRAIN_TIMEOUT_SECS")? {
+            self.server.drain_timeout = Duration::from_secs(secs);
+        }
+        override_from_env(&mut self.database.url, env, "DATABASE_URL")?;
+
+        let pool = &mut self.database.pool;
+        override_from_env(&mut pool.max_size, env, "DB_POOL_MAX_SIZE")?;
+        if let Some(min_idle) = parse_env(env, "DB_POOL_MIN_IDLE")? {
+            pool.min_idle = Some(min_idle);
+        }
+        if let Some(ms) = parse_env(env, "DB_POOL_CONNECTION_TIMEOUT_MS")? {
+            pool.connection_timeout = Duration::from_millis(ms);
+        }
+        if let Some(secs) = parse_env(env, "DB_POOL_IDLE_TIMEOUT_SECS")? {
+            pool.idle_timeout = secs_or_none(secs);
+        }
+        if let Some(secs) = parse_env(env, "DB_POOL_MAX_LIFETIME_SECS")? {
+            pool.max_lifetime = secs_or_none(secs);
+        }
+        override_from_env(&mut pool.queue_size, env, "DB_QUEUE_SIZE")?;
+
+        #[cfg(feature = "sqlite")]
+        {
+            let connection = &mut self.database.sqlite;
+            override_from_env(&mut connection.journal_mode, env, "SQLITE_JOURNAL_MODE")?;
+            if let Some(ms) = parse_env(env, "SQLITE_BUSY_TIMEOUT_MS")? {
+                connection.busy_timeout = Duration::from_millis(ms);
+            }
+            override_from_env(&mut connection.foreign_keys, env, "SQLITE_FOREIGN_KEYS")?;
+            override_from_env(&mut connection.synchronous, env, "SQLITE_SYNCHRONOUS")?;
+            override_from_env(&mut connection.cache_size, env, "SQLITE_CACHE_SIZE")?;
+        }
+        #[cfg(feature = "postgres")]
+        {
+            let connection = &mut self.database.postgres;
+            if let Some(ms) = parse_env(env, "PG_STATEMENT_TIMEOUT_MS")? {
+                connection.statement_timeout = Duration::from_millis(ms);
+            }
+            if let Some(ms) = parse_env(env, "PG_LOCK_TIMEOUT_MS")? {
+                connection.lock_timeout = Duration::from_millis(ms);
+            }
+        }
+
+        let cors = &mut self.cors;
+        for (name, list) in [
+            ("CORS_ALLOWED_ORIGINS", &mut cors.allowed_origins),
+            ("CORS_ALLOWED_METHODS", &mut cors.allowed_methods),
+            ("CORS_ALLOWED_HEADERS", &mut cors.allowed_headers),
+            ("CORS_EXPOSED_HEADERS", &mut cors.exposed_headers),
+        ] {
+            if let Some(value) = env(name) {
+                *list = split_list(&value);
+            }
+        }
+        override_from_env(&mut cors.allow_credentials, env, "CORS_ALLOW_CREDENTIALS")?;
+        if let Some(secs) = parse_env(env, "CORS_MAX_AGE_SECS")? {
+            cors.max_age = secs_or_none(secs);
+        }
+        override_from_env(&mut self.log.level, env, "LOG_LEVEL")?;
+        override_from_env(&mut self.log.format, env, "LOG_FORMAT")?;
+        let access_log = &mut self.access_log;
+        if let Some(path) = env("ACCESS_LOG_PATH") {
+            access_log.path = Some(PathBuf::from(path)).filter(|p| !p.as_os_str().is_empty());
+        }
+        override_from_env(&mut access_log.format, env, "ACCESS_LOG_FORMAT")?;
+        override_from_env(&mut access_log.rotation, env, "ACCESS_LOG_ROTATION")?;
+        override_from_env(
+            &mut access_log.max_size_bytes,
+            env,
+            "ACCESS_LOG_MAX_SIZE_BYTES",
+        )?;
+        override_from_env(&mut access_log.max_files, env, "ACCESS_LOG_MAX_FILES")?;
+        if let Some(endpoint) = env("OTEL_EXPORTER_OTLP_ENDPOINT") {
+            self.telemetry.otlp_endpoint = Some(endpoint).filter(|e| !e.is_empty());
+        }
+        override_from_env(
+            &mut self.telemetry.otlp_protocol,
+            env,
+            "OTEL_EXPORTER_OTLP_PROTOCOL",
+        )?;
+        override_from_env(&mut self.telemetry.service_name, env, "OTEL_SERVICE_NAME")?;
+        override_from_env(&mut self.limits.max_body_bytes, env, "MAX_BODY_BYTES")?;
+        override_from_env(&mut self.limits.max_import_bytes, env, "MAX_IMPORT_BYTES")?;
+        let compression = &mut self.compression;
+        override_from_env(&mut compression.enabled, env, "COMPRESSION_ENABLED")?;
+        override_from_env(
+            &mut compression.min_size_bytes,
+            env,
+            "COMPRESSION_MIN_SIZE_BYTES",
+        )?;
+        if let Some(value) = env("COMPRESSION_CONTENT_TYPES") {
+            compression.content_types = split_list(&value);
+        }
+        override_from_env(&mut self.features.book_store, env, "BOOK_STORE")?;
+        override_from_env(&mut self.features.docs, env, "ENABLE_DOCS")?;
+        override_from_env(&mut self.features.docs_assets, env, "DOCS_ASSETS")?;
+        override_from_env(&mut self.features.diagnostics, env, "ENABLE_DIAGNOSTICS")?;
+        override_from_env(&mut self.features.metrics, env, "ENABLE_METRICS")?;
+
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(bind_address) = cli.bind_address {
+            self.server.bind_address = bind_address;
+        }
+        if let Some(port) = cli.port {
+            self.server.port = port;
+        }
+        if let Some(url) = &cli.database_url {
+            self.database.url = url.clone();
+        }
+        if let Some(max_size) = cli.pool_max_size {
+            self.database.pool.max_size = max_size;
+        }
+        if let Some(origins) = &cli.cors_allowed_origins {
+            self.cors.allowed_origins = origins.clone();
+        }
+        if let Some(level) = cli.log_level {
+            self.log.level = level;
+        }
+        if let Some(format) = cli.log_format {
+            self.log.format = format;
+        }
+        if let Some(path) = &cli.access_log {
+            self.access_log.path = Some(path.clone());
+        }
+        if let Some(max_body_bytes) = cli.max_body_bytes {
+            self.limits.max_body_bytes = max_body_bytes;
+        }
+        if let Some(book_store) = cli.book_store {
+            self.features.book_store = book_store;
+        }
+    }
+
+    /// Checks every setting and reports all problems at once.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        if self.server.port == 0 {
+            problems.push("server.port must be between 1 and 65535".to_string());
+        }
+
+        if self.database.url.is_empty() {
+            problems.push("database.url (DATABASE_URL) must be set".to_string());
+        } else if let Err(e) = db::Backend::from_url(&self.database.url) {
+            problems.push(format!("database.url: {}", e));
+        }
+
+        let pool = &self.database.pool;
+        if pool.max_size == 0 {
+            problems.push("database.pool.max_size must be at least 1".to_string());
+        }
+        if pool
+            .min_idle
+            .is_some_and(|min_idle| min_idle > pool.max_size)
+        {
+            problems.push("database.pool.min_idle must not exceed max_size".to_string());
+        }
+        if pool.connection_timeout.is_zero() {
+            problems.push("database.pool.connection_timeout_ms must be positive".to_string());
+        }
+        if pool.queue_size == 0 {
+            problems.push("database.pool.queue_size must be at least 1".to_string());
+        }
+        if let Err(e) = self.database.connection().validate() {
+            problems.push(format!("database: {}", e));
+        }
+
+        if let Err(e) = Cors::new(&self.cors) {
+            problems.push(format!("cors: {}", e));
+        }
+
+        if let Some(path) = &self.access_log.path {
+            if path.file_name().is_none() {
+                problems.push(format!("access_log.path: {:?} must name a file", path));
+            }
+        }
+
+        if let Some(endpoint) = &self.telemetry.otlp_endpoint {
+            if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+                problems.push(format!(
+                    "telemetry.otlp_endpoint: {:?} must be an http:// or https:// URL",
+                    endpoint
+                ));
+            }
+        }
+
+        if self.limits.max_body_bytes == 0 {
+            problems.push("limits.max_body_bytes must be positive".to_string());
+        }
+        if self.limits.max_import_bytes == 0 {
+            problems.push("limits.max_import_bytes must be positive".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
+
+fn parse_env<T>(
+    env: &impl Fn(&str) -> Option<String>,
+    name: &'static str,
+) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env(name) {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|e: T::Err| ConfigError::Env {
+                name,
+                value,
+                message: e.to_string(),
+            }),
+        None => Ok(None),
+    }
+}
+
+fn override_from_env<T>(
+    field: &mut T,
+    env: &impl Fn(&str) -> Option<String>,
+    name: &'static str,
+) -> Result<(), ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some(value) = parse_env(env, name)? {
+        *field = value;
+    }
+    Ok(())
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn secs_or_none(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// Reads a [`Duration`] given in milliseconds.
+pub(crate) mod millis {
+    use serde::{Deserialize, Deserializer};
+    use std::time::Duration;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+/// Reads a [`Duration`] given in seconds.
+pub(crate) mod secs {
+    use serde::{Deserialize, Deserializer};
+    use std::time::Duration;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_secs)
+    }
+}
+
+/// Reads an optional [`Duration`] given in seconds, where `0` means none.
+pub(crate) mod optional_secs {
+    use serde::{Deserialize, Deserializer};
+    use std::time::Duration;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        u64::deserialize(deserializer).map(super::secs_or_none)
+    }
+}
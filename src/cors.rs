@@ -0,0 +1,266 @@
+use std::sync::Arc;
+use std::time::Duration;
+use warp::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use warp::http::{Method, StatusCode};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::CorsConfig;
+
+/// Cross-origin policy built from [`CorsConfig`].
+///
+/// Unlike `warp::cors`, origins may use a wildcard subdomain such as
+/// `https://*.example.com`, and responses produced by the rejection handler
+/// carry CORS headers too when the policy wraps the recovered routes.
+#[derive(Debug)]
+pub struct Cors {
+    origins: Vec<OriginPattern>,
+    methods: Vec<Method>,
+    headers: AllowedHeaders,
+    exposed_headers: Vec<HeaderName>,
+    allow_credentials: bool,
+    max_age: Option<Duration>,
+}
+
+#[derive(Debug, PartialEq)]
+enum OriginPattern {
+    Any,
+    Exact(String),
+    /// `scheme://*.suffix`, matching any subdomain of `suffix` but not `suffix` itself.
+    Subdomain {
+        scheme: String,
+        suffix: String,
+    },
+}
+
+#[derive(Debug)]
+enum AllowedHeaders {
+    Any,
+    List(Vec<HeaderName>),
+}
+
+impl Cors {
+    pub fn new(config: &CorsConfig) -> Result<Cors, String> {
+        if config.allowed_origins.is_empty() {
+            return Err("allowed_origins must not be empty".to_string());
+        }
+        let origins = config
+            .allowed_origins
+            .iter()
+            .map(|origin| OriginPattern::parse(origin))
+            .collect::<Result<Vec<_>, _>>()?;
+        if config.allow_credentials && origins.contains(&OriginPattern::Any) {
+            return Err("allow_credentials cannot be combined with the \"*\" origin".to_string());
+        }
+
+        let methods = config
+            .allowed_methods
+            .iter()
+            .map(|method| {
+                Method::from_bytes(method.to_uppercase().as_bytes())
+                    .map_err(|_| format!("{:?} is not an HTTP method", method))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let headers = if config.allowed_headers.iter().any(|name| name == "*") {
+            AllowedHeaders::Any
+        } else {
+            AllowedHeaders::List(parse_header_names(&config.allowed_headers)?)
+        };
+
+        Ok(Cors {
+            origins,
+            methods,
+            headers,
+            exposed_headers: parse_header_names(&config.exposed_headers)?,
+            allow_credentials: config.allow_credentials,
+            max_age: config.max_age,
+        })
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|pattern| pattern.matches(origin))
+    }
+
+    fn allows_headers(&self, requested: &str) -> bool {
+        match &self.headers {
+            AllowedHeaders::Any => true,
+            AllowedHeaders::List(allowed) => requested
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .all(|name| {
+                    allowed
+                        .iter()
+                        .any(|allowed| allowed.as_str().eq_ignore_ascii_case(name))
+                }),
+        }
+    }
+
+    fn preflight(&self, origin: &str, method: &str, requested_headers: Option<&str>) -> Response {
+        let method_allowed = Method::from_bytes(method.as_bytes())
+            .map(|method| self.methods.contains(&method))
+            .unwrap_or(false);
+        if !self.allows_origin(origin)
+            || !method_allowed
+            || !self.allows_headers(requested_headers.unwrap_or_default())
+        {
+            return warp::reply::with_status("CORS request forbidden", StatusCode::FORBIDDEN)
+                .into_response();
+        }
+
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        self.insert_origin(headers, origin);
+
+        let methods = self.methods.iter().map(Method::as_str).collect::<Vec<_>>();
+        insert_list(headers, header::ACCESS_CONTROL_ALLOW_METHODS, &methods);
+        match (&self.headers, requested_headers) {
+            // Echo the request so `*` also works for credentialed requests.
+            (AllowedHeaders::Any, Some(requested)) => {
+                insert(headers, header::ACCESS_CONTROL_ALLOW_HEADERS, requested)
+            }
+            (AllowedHeaders::Any, None) => {}
+            (AllowedHeaders::List(allowed), _) => {
+                let allowed = allowed.iter().map(HeaderName::as_str).collect::<Vec<_>>();
+                insert_list(headers, header::ACCESS_CONTROL_ALLOW_HEADERS, &allowed);
+            }
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert(header::ACCESS_CONTROL_MAX_AGE, max_age.as_secs().into());
+        }
+        response
+    }
+
+    fn decorate(&self, origin: Option<&str>, response: &mut Response) {
+        let Some(origin) = origin.filter(|origin| self.allows_origin(origin)) else {
+            return;
+        };
+        let headers = response.headers_mut();
+        self.insert_origin(headers, origin);
+        if !self.exposed_headers.is_empty() {
+            let exposed = self
+                .exposed_headers
+                .iter()
+                .map(HeaderName::as_str)
+                .collect::<Vec<_>>();
+            insert_list(headers, header::ACCESS_CONTROL_EXPOSE_HEADERS, &exposed);
+        }
+    }
+
+    fn insert_origin(&self, headers: &mut HeaderMap, origin: &str) {
+        insert(headers, header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+}
+
+impl OriginPattern {
+    fn parse(origin: &str) -> Result<OriginPattern, String> {
+        let invalid = || {
+            format!(
+                "{:?} is not an origin like https://example.com or https://*.example.com",
+                origin
+            )
+        };
+        if origin == "*" {
+            return Ok(OriginPattern::Any);
+        }
+
+        let (scheme, host) = origin.split_once("://").ok_or_else(invalid)?;
+        if !matches!(scheme, "http" | "https") || host.is_empty() || host.contains('/') {
+            return Err(invalid());
+        }
+        match host.strip_prefix("*.") {
+            Some(suffix) if !suffix.is_empty() && !suffix.contains('*') => {
+                Ok(OriginPattern::Subdomain {
+                    scheme: scheme.to_string(),
+                    suffix: format!(".{}", suffix.to_ascii_lowercase()),
+                })
+            }
+            Some(_) => Err(invalid()),
+            None if host.contains('*') => Err(invalid()),
+            None => Ok(OriginPattern::Exact(origin.to_ascii_lowercase())),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginPattern::Any => true,
+            OriginPattern::Exact(exact) => exact.eq_ignore_ascii_case(origin),
+            OriginPattern::Subdomain { scheme, suffix } => {
+                let origin = origin.to_ascii_lowercase();
+                let Some(host) = origin
+                    .strip_prefix(scheme.as_str())
+                    .and_then(|rest| rest.strip_prefix("://"))
+                else {
+                    return false;
+                };
+                host.strip_suffix(suffix.as_str()).is_some_and(|subdomain| {
+                    !subdomain.is_empty()
+                        && subdomain
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                })
+            }
+        }
+    }
+}
+
+/// Applies `cors` to `filter`: answers preflight requests and adds CORS headers to responses.
+pub fn wrap<F, T>(
+    cors: Arc<Cors>,
+    filter: F,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (T,), Error = Rejection> + Clone + Send + Sync + 'static,
+    T: Reply,
+{
+    let preflight_cors = cors.clone();
+    let preflight = warp::options()
+        .and(warp::header::<String>("origin"))
+        .and(warp::header::<String>("access-control-request-method"))
+        .and(warp::header::optional::<String>(
+            "access-control-request-headers",
+        ))
+        .map(
+            move |origin: String, method: String, headers: Option<String>| {
+                preflight_cors.preflight(&origin, &method, headers.as_deref())
+            },
+        );
+
+    let actual = warp::header::optional::<String>("origin").and(filter).map(
+        move |origin: Option<String>, reply: T| {
+            let mut response = reply.into_response();
+            cors.decorate(origin.as_deref(), &mut response);
+            response
+        },
+    );
+
+    preflight.or(actual).unify()
+}
+
+fn parse_header_names(names: &[String]) -> Result<Vec<HeaderName>, String> {
+    names
+        .iter()
+        .map(|name| {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("{:?} is not a header name", name))
+        })
+        .collect()
+}
+
+fn insert(headers: &mut HeaderMap, name: HeaderName, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name, value);
+    }
+}
+
+fn insert_list(headers: &mut HeaderMap, name: HeaderName, values: &[&str]) {
+    insert(headers, name, &values.join(", "));
+}
@@ -0,0 +1,136 @@
+use serde_json::{Map, Value};
+
+/// Joins the items of an array in a single cell, as `/v1` joins authors.
+pub const LIST_SEPARATOR: &str = "; ";
+
+/// Writes records as RFC 4180 CSV with a header row, in `columns` order.
+///
+/// `value` is a record or an array of them; columns no record has are left
+/// out, so sparse fieldsets give narrower tables.
+pub fn to_string(value: &Value, columns: &[&str]) -> String {
+    let records = match value {
+        Value::Array(items) => items.iter().collect::<Vec<_>>(),
+        record => vec![record],
+    };
+    let columns = columns
+        .iter()
+        .filter(|column| records.iter().any(|record| record.get(**column).is_some()))
+        .collect::<Vec<_>>();
+
+    let mut out = String::new();
+    write_row(&mut out, columns.iter().map(|column| column.to_string()));
+    for record in records {
+        write_record(&mut out, record, &columns);
+    }
+    out
+}
+
+/// Writes the header row of `columns`.
+pub fn write_header(out: &mut String, columns: &[&str]) {
+    write_row(out, columns.iter().map(|column| column.to_string()));
+}
+
+/// Writes `record` as a row of `columns`, leaving missing fields empty.
+pub fn write_record(out: &mut String, record: &Value, columns: &[impl AsRef<str>]) {
+    write_row(
+        out,
+        columns
+            .iter()
+            .map(|column| cell(record.get(column.as_ref()).unwrap_or(&Value::Null))),
+    );
+}
+
+fn write_row(out: &mut String, cells: impl Iterator<Item = String>) {
+    let mut writer = ::csv::WriterBuilder::new()
+        .terminator(::csv::Terminator::CRLF)
+        .from_writer(Vec::new());
+    writer
+        .write_record(cells)
+        .expect("writing to a Vec cannot fail");
+    let row = writer.into_inner().expect("writing to a Vec cannot fail");
+    out.push_str(std::str::from_utf8(&row).expect("cells are UTF-8"));
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items
+            .iter()
+            .map(cell)
+            .collect::<Vec<_>>()
+            .join(LIST_SEPARATOR),
+        value => value.to_string(),
+    }
+}
+
+/// Reads a header row and exactly one record into an object of strings.
+/// Cells of `list_columns` are split on `;` into arrays.
+pub fn from_str(input: &str, list_columns: &[&str]) -> Result<Value, String> {
+    let rows = parse(input)?;
+    let [header, row] = rows.as_slice() else {
+        return Err("expected a header row and one record".to_string());
+    };
+    check_header(header)?;
+    record(header, row, list_columns)
+}
+
+/// Reads a header row and any number of records, like [`from_str`]. A
+/// record that does not fit the header is an error of its own.
+pub fn records(input: &str, list_columns: &[&str]) -> Result<Vec<Result<Value, String>>, String> {
+    let rows = parse(input)?;
+    let Some((header, rows)) = rows.split_first() else {
+        return Err("expected a header row".to_string());
+    };
+    check_header(header)?;
+    Ok(rows
+        .iter()
+        .map(|row| record(header, row, list_columns))
+        .collect())
+}
+
+fn check_header(header: &[String]) -> Result<(), String> {
+    for (i, column) in header.iter().enumerate() {
+        if header[..i].contains(column) {
+            return Err(format!("duplicate column {}", column));
+        }
+    }
+    Ok(())
+}
+
+fn record(header: &[String], row: &[String], list_columns: &[&str]) -> Result<Value, String> {
+    if header.len() != row.len() {
+        return Err("the record and the header have different lengths".to_string());
+    }
+
+    let mut object = Map::new();
+    for (column, cell) in header.iter().zip(row) {
+        let value = if list_columns.contains(&column.as_str()) {
+            Value::Array(
+                cell.split(';')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| Value::String(item.to_string()))
+                    .collect(),
+            )
+        } else {
+            Value::String(cell.clone())
+        };
+        object.insert(column.clone(), value);
+    }
+    Ok(Value::Object(object))
+}
+
+/// Splits RFC 4180 input into rows of cells, ignoring blank lines.
+fn parse(input: &str) -> Result<Vec<Vec<String>>, String> {
+    ::csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(input.strip_prefix('\u{feff}').unwrap_or(input).as_bytes())
+        .records()
+        .map(|row| {
+            row.map(|row| row.iter().map(String::from).collect())
+                .map_err(|e| e.to_string())
+        })
+        .collect()
+}
@@ -1,48 +1,638 @@
+use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
-use diesel::r2d2::{self, ConnectionManager};
-use diesel::result::Error;
-use dotenv::dotenv;
+use diesel::r2d2::{self, CustomizeConnection, R2D2Connection};
+use diesel::result::{DatabaseErrorKind, Error};
+use diesel_migrations::MigrationHarness;
+use serde::Deserialize;
+use std::ops::Deref;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
 
-use crate::models::{Book, NewBook};
+use crate::circuit_breaker::CircuitBreaker;
+use crate::config;
+use crate::errors;
+use crate::idempotency::{self, IdempotentRequest, Outcome};
+use crate::metrics::{time_query, METRICS};
+#[cfg(feature = "postgres")]
+use crate::models::PgSettings;
+#[cfg(feature = "sqlite")]
+use crate::models::PragmaValues;
+use crate::models::{Book, DbDiagnostics, NewBook, PoolStatus};
+use crate::repository::BookRepository;
 
-pub type DbPool = r2d2::Pool<ConnectionManager<SqliteConnection>>;
+#[cfg(not(any(feature = "sqlite", feature = "postgres")))]
+compile_error!("enable the `sqlite` feature, the `postgres` feature or both");
 
-pub fn establish_connection() -> DbPool {
-    dotenv().ok();
-    let database_url = std::env::var("DATABASE_URL").unwrap_or_else(|_| ":memory:".to_string());
-    create_connection_pool(&database_url)
+/// A connection to whichever backend the database URL selects.
+#[derive(diesel::MultiConnection)]
+pub enum DbConnection {
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqliteConnection),
+    #[cfg(feature = "postgres")]
+    Postgres(PgConnection),
 }
 
-pub fn create_connection_pool(database_url: &str) -> DbPool {
-    let manager = ConnectionManager::<SqliteConnection>::new(database_url);
-    r2d2::Pool::builder()
-        .build(manager)
-        .expect("Failed to create pool")
+pub type ConnectionPool = r2d2::Pool<ConnectionManager>;
+
+/// Maximum number of DB jobs that may be running or waiting for a connection.
+pub const DEFAULT_QUEUE_SIZE: usize = 64;
+
+/// Number of times startup tries to open the database before giving up.
+pub const STARTUP_ATTEMPTS: u32 = 8;
+pub const STARTUP_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+pub const STARTUP_MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+type StartupError = Box<dyn std::error::Error + Send + Sync>;
+
+#[cfg(feature = "sqlite")]
+const JOURNAL_MODES: &[&str] = &["DELETE", "TRUNCATE", "PERSIST", "MEMORY", "WAL", "OFF"];
+#[cfg(feature = "sqlite")]
+const SYNCHRONOUS_MODES: &[&str] = &["OFF", "NORMAL", "FULL", "EXTRA"];
+
+/// Sizing and timeouts of the r2d2 pool and the job queue in front of it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolOptions {
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    #[serde(rename = "connection_timeout_ms", with = "config::millis")]
+    pub connection_timeout: Duration,
+    /// `0` in config disables the timeout.
+    #[serde(rename = "idle_timeout_secs", with = "config::optional_secs")]
+    pub idle_timeout: Option<Duration>,
+    /// `0` in config disables the limit.
+    #[serde(rename = "max_lifetime_secs", with = "config::optional_secs")]
+    pub max_lifetime: Option<Duration>,
+    pub queue_size: usize,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        PoolOptions {
+            max_size: 10,
+            min_idle: None,
+            connection_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(10 * 60)),
+            max_lifetime: Some(Duration::from_secs(30 * 60)),
+            queue_size: DEFAULT_QUEUE_SIZE,
+        }
+    }
+}
+
+/// The backends a database URL can select.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+    #[cfg(feature = "postgres")]
+    Postgres,
+}
+
+impl Backend {
+    /// `postgres://` and `postgresql://` URLs select Postgres, anything else
+    /// is an SQLite path. Backends this binary was built without are refused.
+    pub fn from_url(database_url: &str) -> Result<Backend, String> {
+        if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+            #[cfg(feature = "postgres")]
+            return Ok(Backend::Postgres);
+            #[cfg(not(feature = "postgres"))]
+            return Err(
+                "points at Postgres, but this binary was built without the `postgres` feature"
+                    .to_string(),
+            );
+        }
+        #[cfg(feature = "sqlite")]
+        return Ok(Backend::Sqlite);
+        #[cfg(not(feature = "sqlite"))]
+        Err(format!(
+            "{:?} is not a postgres:// URL, and this binary was built without the `sqlite` feature",
+            database_url
+        ))
+    }
+}
+
+/// Opens pool connections to the backend the database URL selects.
+#[derive(Debug)]
+pub struct ConnectionManager {
+    database_url: String,
+    backend: Backend,
+}
+
+impl ConnectionManager {
+    pub fn new(database_url: &str) -> Result<ConnectionManager, String> {
+        Ok(ConnectionManager {
+            database_url: database_url.to_string(),
+            backend: Backend::from_url(database_url)?,
+        })
+    }
+}
+
+impl r2d2::ManageConnection for ConnectionManager {
+    type Connection = DbConnection;
+    type Error = r2d2::Error;
+
+    fn connect(&self) -> Result<DbConnection, r2d2::Error> {
+        match self.backend {
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite => {
+                SqliteConnection::establish(&self.database_url).map(DbConnection::Sqlite)
+            }
+            #[cfg(feature = "postgres")]
+            Backend::Postgres => {
+                PgConnection::establish(&self.database_url).map(DbConnection::Postgres)
+            }
+        }
+        .map_err(r2d2::Error::ConnectionError)
+    }
+
+    fn is_valid(&self, conn: &mut DbConnection) -> Result<(), r2d2::Error> {
+        conn.ping().map_err(r2d2::Error::QueryError)
+    }
+
+    fn has_broken(&self, conn: &mut DbConnection) -> bool {
+        std::thread::panicking() || conn.is_broken()
+    }
+}
+
+/// Settings applied to every connection the pool opens, per backend.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionOptions {
+    #[cfg(feature = "sqlite")]
+    pub sqlite: SqliteOptions,
+    #[cfg(feature = "postgres")]
+    pub postgres: PostgresOptions,
+}
+
+impl ConnectionOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        #[cfg(feature = "sqlite")]
+        self.sqlite.validate()?;
+        Ok(())
+    }
+}
+
+impl CustomizeConnection<DbConnection, r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut DbConnection) -> Result<(), r2d2::Error> {
+        match conn {
+            #[cfg(feature = "sqlite")]
+            DbConnection::Sqlite(conn) => self.sqlite.on_acquire(conn),
+            #[cfg(feature = "postgres")]
+            DbConnection::Postgres(conn) => self.postgres.on_acquire(conn),
+        }
+    }
+}
+
+/// SQLite pragmas applied to every connection the pool opens.
+#[cfg(feature = "sqlite")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SqliteOptions {
+    pub journal_mode: String,
+    #[serde(rename = "busy_timeout_ms", with = "config::millis")]
+    pub busy_timeout: Duration,
+    pub foreign_keys: bool,
+    pub synchronous: String,
+    /// Page cache size; negative values are KiB, positive values are pages.
+    pub cache_size: i64,
+}
+
+#[cfg(feature = "sqlite")]
+impl Default for SqliteOptions {
+    fn default() -> Self {
+        SqliteOptions {
+            journal_mode: "WAL".to_string(),
+            busy_timeout: Duration::from_secs(5),
+            foreign_keys: true,
+            synchronous: "NORMAL".to_string(),
+            cache_size: -64_000,
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if !JOURNAL_MODES.contains(&self.journal_mode.to_uppercase().as_str()) {
+            return Err(format!("unsupported journal mode {:?}", self.journal_mode));
+        }
+        if !SYNCHRONOUS_MODES.contains(&self.synchronous.to_uppercase().as_str()) {
+            return Err(format!(
+                "unsupported synchronous mode {:?}",
+                self.synchronous
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl CustomizeConnection<SqliteConnection, r2d2::Error> for SqliteOptions {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), r2d2::Error> {
+        // busy_timeout goes first so that switching to WAL waits out other writers.
+        conn.batch_execute(&format!(
+            "PRAGMA busy_timeout = {}; \
+             PRAGMA journal_mode = {}; \
+             PRAGMA synchronous = {}; \
+             PRAGMA foreign_keys = {}; \
+             PRAGMA cache_size = {};",
+            self.busy_timeout.as_millis(),
+            self.journal_mode,
+            self.synchronous,
+            if self.foreign_keys { "ON" } else { "OFF" },
+            self.cache_size,
+        ))
+        .map_err(r2d2::Error::QueryError)
+    }
+}
+
+/// Session settings applied to every Postgres connection the pool opens.
+#[cfg(feature = "postgres")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PostgresOptions {
+    #[serde(rename = "statement_timeout_ms", with = "config::millis")]
+    pub statement_timeout: Duration,
+    #[serde(rename = "lock_timeout_ms", with = "config::millis")]
+    pub lock_timeout: Duration,
+}
+
+#[cfg(feature = "postgres")]
+impl Default for PostgresOptions {
+    fn default() -> Self {
+        PostgresOptions {
+            statement_timeout: Duration::from_secs(30),
+            lock_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl CustomizeConnection<PgConnection, r2d2::Error> for PostgresOptions {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), r2d2::Error> {
+        conn.batch_execute(&format!(
+            "SET statement_timeout = {}; SET lock_timeout = {};",
+            self.statement_timeout.as_millis(),
+            self.lock_timeout.as_millis(),
+        ))
+        .map_err(r2d2::Error::QueryError)
+    }
+}
+
+/// Connection pool whose queries run on tokio's blocking thread pool.
+///
+/// Jobs beyond the queue size are refused with [`errors::Error::Unavailable`]
+/// rather than piling up behind the r2d2 connection timeout, and so are all
+/// jobs while the circuit breaker considers the database unhealthy.
+pub struct DbPool {
+    pool: ConnectionPool,
+    queue: Semaphore,
+    queue_capacity: usize,
+    breaker: CircuitBreaker,
+}
+
+impl DbPool {
+    pub fn new(pool: ConnectionPool, queue_size: usize, breaker: CircuitBreaker) -> Self {
+        DbPool {
+            pool,
+            queue: Semaphore::new(queue_size),
+            queue_capacity: queue_size,
+            breaker,
+        }
+    }
+
+    /// Checks out a connection directly, bypassing the queue and circuit breaker.
+    pub fn get(&self) -> Result<r2d2::PooledConnection<ConnectionManager>, r2d2::PoolError> {
+        self.pool.get()
+    }
+
+    pub fn status(&self) -> PoolStatus {
+        let state = self.pool.state();
+        PoolStatus {
+            max_size: self.pool.max_size(),
+            min_idle: self.pool.min_idle(),
+            connections: state.connections,
+            idle_connections: state.idle_connections,
+            connection_timeout_ms: self.pool.connection_timeout().as_millis() as u64,
+            queued_jobs: self.queue_capacity - self.queue.available_permits(),
+        }
+    }
+
+    /// Runs `query` with a pooled connection off the async executor.
+    pub async fn run<T, E, F>(&self, query: F) -> Result<T, errors::Error>
+    where
+        F: FnOnce(&mut DbConnection) -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: Send + 'static,
+        errors::Error: From<E>,
+    {
+        let _permit = self
+            .queue
+            .try_acquire()
+            .map_err(|_| errors::Error::Unavailable(errors::RETRY_AFTER_SECS))?;
+        self.breaker.allow().map_err(|remaining| {
+            // Round up so clients never retry before the breaker lets a trial through.
+            let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+            errors::Error::Unavailable(secs.max(1))
+        })?;
+        let pool = self.pool.clone();
+        // Keep the caller's subscriber and request span current on the blocking thread.
+        let dispatch = tracing::dispatcher::get_default(Clone::clone);
+        let span = tracing::Span::current();
+
+        let result = tokio::task::spawn_blocking(move || {
+            tracing::dispatcher::with_default(&dispatch, || {
+                let _span = span.entered();
+                let checkout = Instant::now();
+                let conn = pool.get();
+                METRICS.observe_pool_wait(checkout.elapsed());
+                let conn = &mut conn?;
+                query(conn).map_err(errors::Error::from)
+            })
+        })
+        .await
+        .unwrap_or_else(|e| Err(errors::Error::Internal(e.to_string())));
+
+        match &result {
+            Err(e) if indicates_outage(e) => self.breaker.record_failure(),
+            _ => self.breaker.record_success(),
+        }
+        result
+    }
 }
 
-pub fn create_book(pool: &DbPool, new_book: NewBook) -> Result<Book, Error> {
+impl Deref for DbPool {
+    type Target = ConnectionPool;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pool
+    }
+}
+
+impl BookRepository for DbPool {
+    async fn list(&self) -> Result<Vec<Book>, errors::Error> {
+        self.run(get_all_books).await
+    }
+
+    async fn get(&self, id: i32) -> Result<Book, errors::Error> {
+        self.run(move |conn| get_book(conn, id)).await
+    }
+
+    async fn page(&self, after: i32, limit: i64) -> Result<Vec<Book>, errors::Error> {
+        self.run(move |conn| get_books_page(conn, after, limit))
+            .await
+    }
+
+    async fn count(&self) -> Result<i64, errors::Error> {
+        self.run(count_books).await
+    }
+
+    async fn create(
+        &self,
+        new_book: NewBook,
+        request: Option<IdempotentRequest>,
+    ) -> Result<Outcome<Book>, errors::Error> {
+        self.run(move |conn| {
+            idempotency::execute(conn, request.as_ref(), |conn| create_book(conn, new_book))
+        })
+        .await
+    }
+
+    async fn create_many(&self, new_books: Vec<NewBook>) -> Result<Vec<Book>, errors::Error> {
+        self.run(move |conn| create_books(conn, &new_books)).await
+    }
+
+    async fn update(&self, id: i32, updated_book: NewBook) -> Result<Book, errors::Error> {
+        self.run(move |conn| update_book(conn, id, updated_book))
+            .await
+    }
+
+    async fn delete(&self, id: i32) -> Result<(), errors::Error> {
+        self.run(move |conn| delete_book(conn, id)).await
+    }
+}
+
+/// Whether an error means the database itself is unhealthy, as opposed to a bad request.
+fn indicates_outage(error: &errors::Error) -> bool {
+    match error {
+        errors::Error::PoolError(_) => true,
+        errors::Error::DatabaseError(Error::DatabaseError(kind, _)) => !matches!(
+            kind,
+            DatabaseErrorKind::UniqueViolation
+                | DatabaseErrorKind::ForeignKeyViolation
+                | DatabaseErrorKind::NotNullViolation
+                | DatabaseErrorKind::CheckViolation
+                | DatabaseErrorKind::SerializationFailure
+        ),
+        _ => false,
+    }
+}
+
+/// Opens the configured database and applies pending migrations.
+///
+/// A database that is locked or not yet reachable is retried with
+/// exponential backoff before startup gives up.
+pub fn establish_connection(database: &config::DatabaseConfig) -> DbPool {
+    let mut backoff = STARTUP_INITIAL_BACKOFF;
+    for attempt in 1.. {
+        match connect(database) {
+            Ok(pool) => return pool,
+            Err(e) if attempt < STARTUP_ATTEMPTS => {
+                tracing::warn!(
+                    attempt,
+                    max_attempts = STARTUP_ATTEMPTS,
+                    error = %e,
+                    retry_in = ?backoff,
+                    "database not ready"
+                );
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(STARTUP_MAX_BACKOFF);
+            }
+            Err(e) => panic!("Failed to open database {}: {}", database.url, e),
+        }
+    }
+    unreachable!()
+}
+
+fn connect(database: &config::DatabaseConfig) -> Result<DbPool, StartupError> {
+    let pool = create_connection_pool(&database.url, &database.pool, &database.connection())?;
+
+    run_migrations(&mut *pool.get()?)?;
+
+    Ok(pool)
+}
+
+pub fn create_connection_pool(
+    database_url: &str,
+    pool_options: &PoolOptions,
+    connection_options: &ConnectionOptions,
+) -> Result<DbPool, StartupError> {
+    // Each connection to `:memory:` opens a separate, empty database.
+    let max_size = if database_url == ":memory:" {
+        1
+    } else {
+        pool_options.max_size
+    };
+
+    let manager = ConnectionManager::new(database_url)?;
+    let pool = r2d2::Pool::builder()
+        .max_size(max_size)
+        .min_idle(pool_options.min_idle)
+        .connection_timeout(pool_options.connection_timeout)
+        .idle_timeout(pool_options.idle_timeout)
+        .max_lifetime(pool_options.max_lifetime)
+        .connection_customizer(Box::new(connection_options.clone()))
+        .build(manager)?;
+
+    Ok(DbPool::new(
+        pool,
+        pool_options.queue_size,
+        CircuitBreaker::default(),
+    ))
+}
+
+/// Applies the pending migrations of `conn`'s backend.
+pub fn run_migrations(conn: &mut DbConnection) -> Result<(), StartupError> {
+    match conn {
+        #[cfg(feature = "sqlite")]
+        DbConnection::Sqlite(conn) => conn.run_pending_migrations(crate::SQLITE_MIGRATIONS)?,
+        #[cfg(feature = "postgres")]
+        DbConnection::Postgres(conn) => conn.run_pending_migrations(crate::POSTGRES_MIGRATIONS)?,
+    };
+    Ok(())
+}
+
+/// Reads the pragmas or session settings in effect on `conn`, whichever its
+/// backend has.
+pub fn get_diagnostics(conn: &mut DbConnection, pool: PoolStatus) -> Result<DbDiagnostics, Error> {
+    let mut diagnostics = DbDiagnostics {
+        #[cfg(feature = "sqlite")]
+        pragmas: None,
+        #[cfg(feature = "postgres")]
+        settings: None,
+        pool,
+    };
+    match conn {
+        #[cfg(feature = "sqlite")]
+        DbConnection::Sqlite(conn) => diagnostics.pragmas = Some(get_pragma_values(conn)?),
+        #[cfg(feature = "postgres")]
+        DbConnection::Postgres(conn) => diagnostics.settings = Some(get_settings(conn)?),
+    }
+    Ok(diagnostics)
+}
+
+/// Reads the pragma values in effect on `conn`.
+#[cfg(feature = "sqlite")]
+pub fn get_pragma_values(conn: &mut SqliteConnection) -> Result<PragmaValues, Error> {
+    diesel::sql_query(
+        "SELECT (SELECT journal_mode FROM pragma_journal_mode) AS journal_mode, \
+                (SELECT timeout FROM pragma_busy_timeout) AS busy_timeout_ms, \
+                (SELECT foreign_keys FROM pragma_foreign_keys) AS foreign_keys, \
+                (SELECT CASE synchronous WHEN 0 THEN 'OFF' WHEN 1 THEN 'NORMAL' \
+                    WHEN 2 THEN 'FULL' ELSE 'EXTRA' END FROM pragma_synchronous) AS synchronous, \
+                (SELECT cache_size FROM pragma_cache_size) AS cache_size",
+    )
+    .get_result(conn)
+}
+
+/// Runs a trivial query to prove the connection works.
+pub fn ping(conn: &mut DbConnection) -> Result<(), Error> {
+    conn.batch_execute("SELECT 1")
+}
+
+/// Counts the embedded migrations not yet applied to the database.
+pub fn pending_migrations(conn: &mut DbConnection) -> Result<usize, errors::Error> {
+    let pending = match conn {
+        #[cfg(feature = "sqlite")]
+        DbConnection::Sqlite(conn) => conn
+            .pending_migrations(crate::SQLITE_MIGRATIONS)
+            .map(|pending| pending.len()),
+        #[cfg(feature = "postgres")]
+        DbConnection::Postgres(conn) => conn
+            .pending_migrations(crate::POSTGRES_MIGRATIONS)
+            .map(|pending| pending.len()),
+    };
+    pending.map_err(|e| errors::Error::Internal(e.to_string()))
+}
+
+/// Moves an SQLite WAL into the database file and truncates it, so a stopped
+/// server leaves a self-contained database behind. Postgres has nothing to do.
+pub fn checkpoint_wal(conn: &mut DbConnection) -> Result<(), Error> {
+    match conn {
+        #[cfg(feature = "sqlite")]
+        DbConnection::Sqlite(conn) => conn.batch_execute("PRAGMA wal_checkpoint(TRUNCATE);"),
+        #[cfg(feature = "postgres")]
+        DbConnection::Postgres(_) => Ok(()),
+    }
+}
+
+/// Reads the session settings in effect on `conn`.
+#[cfg(feature = "postgres")]
+pub fn get_settings(conn: &mut PgConnection) -> Result<PgSettings, Error> {
+    diesel::sql_query(
+        "SELECT current_setting('server_version') AS server_version, \
+                current_setting('statement_timeout') AS statement_timeout, \
+                current_setting('lock_timeout') AS lock_timeout",
+    )
+    .get_result(conn)
+}
+
+pub fn create_book(conn: &mut DbConnection, new_book: NewBook) -> Result<Book, Error> {
     use crate::schema::books::dsl::*;
-    let conn = &mut pool.get().unwrap();
-    diesel::insert_into(books).values(&new_book).execute(conn)?;
 
-    books.order(id.desc()).first(conn)
+    time_query("create_book", || {
+        diesel::insert_into(books)
+            .values(&new_book)
+            .get_result(conn)
+    })
 }
 
-pub fn get_all_books(pool: &DbPool) -> Result<Vec<Book>, Error> {
+/// Inserts `new_books` in one transaction, so either all are created or none.
+pub fn create_books(conn: &mut DbConnection, new_books: &[NewBook]) -> Result<Vec<Book>, Error> {
     use crate::schema::books::dsl::*;
-    let conn = &mut pool.get().unwrap();
 
-    books.load::<Book>(conn)
+    let insert = diesel::insert_into(books).values(new_books);
+    time_query("create_books", || {
+        // Multi-row inserts are built per backend.
+        conn.transaction(|conn| match conn {
+            #[cfg(feature = "sqlite")]
+            DbConnection::Sqlite(conn) => insert.get_results(conn),
+            #[cfg(feature = "postgres")]
+            DbConnection::Postgres(conn) => insert.get_results(conn),
+        })
+    })
 }
 
-pub fn get_book(pool: &DbPool, book_id: i32) -> Result<Book, Error> {
+pub fn get_all_books(conn: &mut DbConnection) -> Result<Vec<Book>, Error> {
     use crate::schema::books::dsl::*;
-    let conn = &mut pool.get().unwrap();
 
-    let book = books
-        .filter(id.eq(book_id))
-        .first::<Book>(conn)
-        .optional()?;
+    time_query("get_all_books", || books.load::<Book>(conn))
+}
+
+/// Up to `limit` books with ids above `after`, in id order.
+pub fn get_books_page(conn: &mut DbConnection, after: i32, limit: i64) -> Result<Vec<Book>, Error> {
+    use crate::schema::books::dsl::*;
+
+    time_query("get_books_page", || {
+        books
+            .filter(id.gt(after))
+            .order(id.asc())
+            .limit(limit)
+            .load::<Book>(conn)
+    })
+}
+
+pub fn count_books(conn: &mut DbConnection) -> Result<i64, Error> {
+    use crate::schema::books::dsl::*;
+
+    time_query("count_books", || books.count().get_result(conn))
+}
+
+pub fn get_book(conn: &mut DbConnection, book_id: i32) -> Result<Book, Error> {
+    use crate::schema::books::dsl::*;
+
+    let book = time_query("get_book", || {
+        books.filter(id.eq(book_id)).first::<Book>(conn).optional()
+    })?;
 
     match book {
         Some(book) => Ok(book),
@@ -50,31 +640,26 @@ pub fn get_book(pool: &DbPool, book_id: i32) -> Result<Book, Error> {
     }
 }
 
-pub fn update_book(pool: &DbPool, book_id: i32, updated_book: NewBook) -> Result<Book, Error> {
+pub fn update_book(
+    conn: &mut DbConnection,
+    book_id: i32,
+    updated_book: NewBook,
+) -> Result<Book, Error> {
     use crate::schema::books::dsl::*;
-    let conn = &mut pool.get().unwrap();
-    let target = books.filter(id.eq(book_id));
-
-    if target.first::<Book>(conn).optional()?.is_none() {
-        return Err(Error::NotFound);
-    }
-
-    diesel::update(target)
-        .set((
-            title.eq(updated_book.title),
-            author.eq(updated_book.author),
-            date_published.eq(updated_book.date_published),
-            cover_image.eq(updated_book.cover_image),
-        ))
-        .execute(conn)?;
 
-    target.first(conn)
+    // `get_result` reports `NotFound` when no row matched the filter.
+    time_query("update_book", || {
+        diesel::update(books.filter(id.eq(book_id)))
+            .set(&updated_book)
+            .get_result(conn)
+    })
 }
 
-pub fn delete_book(pool: &DbPool, book_id: i32) -> Result<(), Error> {
+pub fn delete_book(conn: &mut DbConnection, book_id: i32) -> Result<(), Error> {
     use crate::schema::books::dsl::*;
-    let conn = &mut pool.get().unwrap();
-    let affected_rows = diesel::delete(books.filter(id.eq(book_id))).execute(conn)?;
+    let affected_rows = time_query("delete_book", || {
+        diesel::delete(books.filter(id.eq(book_id))).execute(conn)
+    })?;
 
     if affected_rows == 0 {
         return Err(Error::NotFound);
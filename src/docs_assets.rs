@@ -0,0 +1,133 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use warp::http::header::{self, HeaderValue};
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::DocsAssets;
+
+pub const SWAGGER_UI_VERSION: &str = "5.17.14";
+pub const REDOC_VERSION: &str = "2.0.0-rc.72";
+
+/// Assets are revalidated daily; unchanged ones answer `304 Not Modified`.
+const CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// A file served under `/docs/assets/`.
+pub struct Asset {
+    pub name: &'static str,
+    pub content_type: &'static str,
+    pub bytes: &'static [u8],
+}
+
+/// The Swagger UI and Redoc files vendored under `assets/docs/`; see
+/// `scripts/fetch-docs-assets.sh`.
+pub const BUNDLED: &[Asset] = &[
+    Asset {
+        name: "swagger-ui.css",
+        content_type: "text/css; charset=utf-8",
+        bytes: include_bytes!("../assets/docs/swagger-ui.css"),
+    },
+    Asset {
+        name: "swagger-ui-bundle.js",
+        content_type: "text/javascript; charset=utf-8",
+        bytes: include_bytes!("../assets/docs/swagger-ui-bundle.js"),
+    },
+    Asset {
+        name: "redoc.standalone.js",
+        content_type: "text/javascript; charset=utf-8",
+        bytes: include_bytes!("../assets/docs/redoc.standalone.js"),
+    },
+];
+
+/// Where the docs pages load their scripts and styles from.
+pub struct Urls {
+    pub swagger_ui_css: String,
+    pub swagger_ui_js: String,
+    pub redoc_js: String,
+    /// Redoc's preferred fonts, which only a CDN can provide.
+    pub fonts_css: Option<String>,
+}
+
+impl Urls {
+    pub fn new(assets: DocsAssets) -> Urls {
+        match assets {
+            DocsAssets::Bundled => Urls {
+                swagger_ui_css: "/docs/assets/swagger-ui.css".to_string(),
+                swagger_ui_js: "/docs/assets/swagger-ui-bundle.js".to_string(),
+                redoc_js: "/docs/assets/redoc.standalone.js".to_string(),
+                fonts_css: None,
+            },
+            DocsAssets::Cdn => Urls {
+                swagger_ui_css: format!(
+                    "https://unpkg.com/swagger-ui-dist@{}/swagger-ui.css",
+                    SWAGGER_UI_VERSION
+                ),
+                swagger_ui_js: format!(
+                    "https://unpkg.com/swagger-ui-dist@{}/swagger-ui-bundle.js",
+                    SWAGGER_UI_VERSION
+                ),
+                redoc_js: format!(
+                    "https://cdn.redoc.ly/redoc/v{}/bundles/redoc.standalone.js",
+                    REDOC_VERSION
+                ),
+                fonts_css: Some(
+                    "https://fonts.googleapis.com/css?family=Montserrat:300,400,700|Roboto:300,400,700"
+                        .to_string(),
+                ),
+            },
+        }
+    }
+}
+
+/// Serves `GET /docs/assets/{name}` from `assets`, with a content hash as `ETag`.
+pub fn serve(
+    assets: &'static [Asset],
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let assets = Arc::new(
+        assets
+            .iter()
+            .map(|asset| {
+                let etag = format!("\"{}\"", hex::encode(Sha256::digest(asset.bytes)));
+                (asset.name, (asset, etag))
+            })
+            .collect::<HashMap<_, _>>(),
+    );
+
+    warp::path!("docs" / "assets" / String)
+        .and(warp::get())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and_then(move |name: String, if_none_match: Option<String>| {
+            let assets = assets.clone();
+            async move {
+                let (asset, etag) = assets
+                    .get(name.as_str())
+                    .ok_or_else(warp::reject::not_found)?;
+                let unchanged = if_none_match.is_some_and(|tags| {
+                    tags.split(',')
+                        .any(|tag| tag.trim() == etag || tag.trim() == "*")
+                });
+                let mut response = if unchanged {
+                    StatusCode::NOT_MODIFIED.into_response()
+                } else {
+                    let mut response = Response::new(asset.bytes.into());
+                    response.headers_mut().insert(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(asset.content_type),
+                    );
+                    response
+                };
+                let headers = response.headers_mut();
+                headers.insert(
+                    header::CACHE_CONTROL,
+                    HeaderValue::from_static(CACHE_CONTROL),
+                );
+                headers.insert(
+                    header::ETAG,
+                    HeaderValue::from_str(etag).expect("hex ETags are valid headers"),
+                );
+                Ok::<_, Rejection>(response)
+            }
+        })
+}
@@ -1,39 +1,180 @@
 use thiserror::Error;
+use warp::http::{header, StatusCode};
 use warp::reject::Reject;
+use warp::Reply;
+
+use crate::models::ErrorBody;
+
+/// Seconds clients are asked to wait before retrying a 503 response, unless
+/// the error says otherwise.
+pub const RETRY_AFTER_SECS: u64 = 1;
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[allow(clippy::enum_variant_names)]
     #[error("database error: {0}")]
-    DatabaseError(#[from] diesel::result::Error),
+    DatabaseError(diesel::result::Error),
     #[error("not found")]
     NotFound,
     #[error("invalid data")]
     InvalidData,
+    #[error("invalid query parameters")]
+    InvalidQuery,
+    #[error("idempotency key reused with a different request")]
+    IdempotencyKeyReused,
+    #[error("connection pool error: {0}")]
+    PoolError(#[from] diesel::r2d2::PoolError),
+    #[error("request body too large")]
+    PayloadTooLarge,
+    #[error("request body length required")]
+    LengthRequired,
+    #[error("no acceptable representation")]
+    NotAcceptable,
+    #[error("unsupported request body type")]
+    UnsupportedMediaType,
+    /// Carries the route's supported methods for the `Allow` header.
+    #[error("method not allowed")]
+    MethodNotAllowed(&'static str),
+    /// Carries the seconds for the `Retry-After` header.
+    #[error("database unavailable")]
+    Unavailable(u64),
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+impl From<diesel::result::Error> for Error {
+    fn from(error: diesel::result::Error) -> Self {
+        match error {
+            diesel::result::Error::NotFound => Error::NotFound,
+            error => Error::DatabaseError(error),
+        }
+    }
 }
 
 impl Reject for Error {}
 
+/// Attached to error responses so that `request_id::wrap` can add the request
+/// ID to the body and log the underlying cause alongside it.
+#[derive(Clone, Debug)]
+pub struct ErrorContext {
+    pub message: &'static str,
+    /// Server-side detail that is logged but never sent to the client.
+    pub cause: Option<String>,
+}
+
 pub async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, warp::Rejection> {
     if let Some(error) = err.find::<Error>() {
         let (code, message) = match error {
-            Error::DatabaseError(_) => (
-                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-                "Internal Server Error",
-            ),
-            Error::NotFound => (warp::http::StatusCode::NOT_FOUND, "Not Found"),
-            Error::InvalidData => (warp::http::StatusCode::BAD_REQUEST, "Invalid Data"),
+            Error::DatabaseError(_) | Error::Internal(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
+            }
+            Error::NotFound => (StatusCode::NOT_FOUND, "Not Found"),
+            Error::InvalidData => (StatusCode::BAD_REQUEST, "Invalid Data"),
+            Error::InvalidQuery => (StatusCode::BAD_REQUEST, "Invalid Query"),
+            Error::IdempotencyKeyReused => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "Idempotency Key Reused")
+            }
+            Error::PayloadTooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "Payload Too Large"),
+            Error::LengthRequired => (StatusCode::LENGTH_REQUIRED, "Length Required"),
+            Error::NotAcceptable => (StatusCode::NOT_ACCEPTABLE, "Not Acceptable"),
+            Error::UnsupportedMediaType => {
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Unsupported Media Type")
+            }
+            Error::MethodNotAllowed(_) => (StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed"),
+            Error::PoolError(_) | Error::Unavailable(_) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable")
+            }
         };
-        Ok(warp::reply::with_status(message.to_string(), code))
+        let cause = code.is_server_error().then(|| error.to_string());
+        let mut response = error_response(code, message, cause);
+        if code == StatusCode::SERVICE_UNAVAILABLE {
+            let retry_after = match error {
+                Error::Unavailable(secs) => *secs,
+                _ => RETRY_AFTER_SECS,
+            };
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after.into());
+        }
+        if let Error::MethodNotAllowed(allow) = error {
+            response
+                .headers_mut()
+                .insert(header::ALLOW, header::HeaderValue::from_static(allow));
+        }
+        Ok(response)
     } else if err.is_not_found() {
-        Ok(warp::reply::with_status(
-            "Not Found".to_string(),
-            warp::http::StatusCode::NOT_FOUND,
+        Ok(error_response(StatusCode::NOT_FOUND, "Not Found", None))
+    } else if err
+        .find::<warp::filters::body::BodyDeserializeError>()
+        .is_some()
+    {
+        Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid Data",
+            None,
+        ))
+    } else if err.find::<warp::reject::InvalidQuery>().is_some() {
+        Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid Query",
+            None,
+        ))
+    } else if err.find::<warp::reject::InvalidHeader>().is_some()
+        || err.find::<warp::reject::MissingHeader>().is_some()
+    {
+        // Includes a `Content-Length` that is not a number.
+        Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid Header",
+            None,
+        ))
+    } else if err.find::<warp::reject::LengthRequired>().is_some() {
+        Ok(error_response(
+            StatusCode::LENGTH_REQUIRED,
+            "Length Required",
+            None,
+        ))
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        Ok(error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "Payload Too Large",
+            None,
+        ))
+    } else if err.find::<warp::reject::UnsupportedMediaType>().is_some() {
+        Ok(error_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Unsupported Media Type",
+            None,
+        ))
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        // Checked last: a route's own rejection explains more than another route's method.
+        Ok(error_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Method Not Allowed",
+            None,
         ))
     } else {
-        Ok(warp::reply::with_status(
-            "Internal Server Error".to_string(),
-            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        Ok(error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal Server Error",
+            Some(format!("{:?}", err)),
         ))
     }
 }
+
+fn error_response(
+    code: StatusCode,
+    message: &'static str,
+    cause: Option<String>,
+) -> warp::reply::Response {
+    let body = ErrorBody {
+        status: code.as_u16(),
+        error: message.to_string(),
+        request_id: None,
+    };
+    let mut response = warp::reply::with_status(warp::reply::json(&body), code).into_response();
+    response
+        .extensions_mut()
+        .insert(ErrorContext { message, cause });
+    response
+}
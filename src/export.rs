@@ -0,0 +1,74 @@
+use serde::Deserialize;
+use time::OffsetDateTime;
+use utoipa::{IntoParams, ToSchema};
+use warp::http::header::{self, HeaderValue};
+use warp::hyper::Body;
+use warp::reply::Response;
+
+use crate::formats::Format;
+
+/// The `format` query parameter of `GET /books/export`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    /// The file format; JSON by default.
+    #[serde(default)]
+    #[param(inline)]
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Ndjson,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn format(self) -> Format {
+        match self {
+            ExportFormat::Json => Format::Json,
+            ExportFormat::Ndjson => Format::NdJson,
+            ExportFormat::Csv => Format::Csv,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+/// The headers of an export in `format`, without reading any books.
+pub fn head(format: ExportFormat) -> Response {
+    let mut response = Response::new(Body::empty());
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.format().content_type()),
+    );
+    attachment(response, format)
+}
+
+/// Marks a streamed list as a download named after today's date, e.g.
+/// `books-2026-10-18.csv`.
+pub fn attachment(mut response: Response, format: ExportFormat) -> Response {
+    let filename = format!(
+        "books-{}.{}",
+        OffsetDateTime::now_utc().date(),
+        format.extension()
+    );
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename))
+            .expect("filenames are valid headers"),
+    );
+    // The format comes from the query, not `Accept`.
+    headers.remove(header::VARY);
+    response
+}
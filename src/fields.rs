@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::IntoParams;
+
+use crate::errors::Error;
+
+/// The `fields` and `expand` query parameters of the book routes.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FieldsQuery {
+    /// Comma-separated fields to return, e.g. `id,title`; all by default.
+    #[param(example = "id,title")]
+    pub fields: Option<String>,
+    /// Comma-separated related resources to embed. Books have none yet, so
+    /// any value is rejected.
+    pub expand: Option<String>,
+}
+
+/// The fields a response is trimmed to, or `None` for all of them.
+pub struct Fieldset(Option<Vec<String>>);
+
+impl FieldsQuery {
+    /// Checks the requested names against a resource's `fields` and `relations`.
+    pub fn parse(&self, fields: &[&str], relations: &[&str]) -> Result<Fieldset, Error> {
+        if let Some(expand) = &self.expand {
+            check(expand, relations)?;
+        }
+        let selected = match &self.fields {
+            Some(list) => Some(check(list, fields)?.into_iter().map(String::from).collect()),
+            None => None,
+        };
+        Ok(Fieldset(selected))
+    }
+}
+
+fn check<'a>(list: &'a str, known: &[&str]) -> Result<Vec<&'a str>, Error> {
+    let names = list.split(',').map(str::trim).collect::<Vec<_>>();
+    if names.iter().all(|name| known.contains(name)) {
+        Ok(names)
+    } else {
+        Err(Error::InvalidQuery)
+    }
+}
+
+impl Fieldset {
+    pub fn is_all(&self) -> bool {
+        self.0.is_none()
+    }
+
+    /// Whether `field` is selected.
+    pub fn includes(&self, field: &str) -> bool {
+        self.0
+            .as_ref()
+            .is_none_or(|fields| fields.iter().any(|selected| selected == field))
+    }
+
+    /// Serializes `value`, keeping only the selected fields of each object in it.
+    pub fn apply<T: Serialize>(&self, value: &T) -> Value {
+        let value = serde_json::to_value(value).expect("books serialize");
+        match &self.0 {
+            None => value,
+            Some(fields) => select(value, fields),
+        }
+    }
+}
+
+fn select(value: Value, fields: &[String]) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(name, _)| fields.contains(name))
+                .collect(),
+        ),
+        Value::Array(items) => {
+            Value::Array(items.into_iter().map(|item| select(item, fields)).collect())
+        }
+        value => value,
+    }
+}
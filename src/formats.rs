@@ -0,0 +1,293 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::openapi::{Content, RefOr};
+use warp::http::header::{self, HeaderValue};
+use warp::hyper::body::Bytes;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::compression;
+use crate::errors::Error;
+use crate::fields::Fieldset;
+use crate::{csv, msgpack, xml, yaml};
+
+/// A representation of book resources, in the server's order of preference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+    Xml,
+    Yaml,
+    MsgPack,
+    NdJson,
+}
+
+impl Format {
+    const ALL: [Format; 6] = [
+        Format::Json,
+        Format::Csv,
+        Format::Xml,
+        Format::Yaml,
+        Format::MsgPack,
+        Format::NdJson,
+    ];
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Xml => "application/xml",
+            Format::Yaml => "application/yaml",
+            Format::MsgPack => "application/msgpack",
+            Format::NdJson => "application/x-ndjson",
+        }
+    }
+
+    /// Media types read as this format; the first is the one it is sent as.
+    fn media_types(self) -> &'static [&'static str] {
+        match self {
+            Format::Json => &["application/json"],
+            Format::Csv => &["text/csv"],
+            Format::Xml => &["application/xml", "text/xml"],
+            Format::Yaml => &["application/yaml", "application/x-yaml", "text/yaml"],
+            Format::MsgPack => &["application/msgpack", "application/x-msgpack"],
+            Format::NdJson => &["application/x-ndjson", "application/jsonl"],
+        }
+    }
+
+    /// Whether lists in this format are written a page of rows at a time;
+    /// the others need the whole list up front.
+    pub fn streams(self) -> bool {
+        matches!(self, Format::Json | Format::NdJson | Format::Csv)
+    }
+
+    /// Picks the format an `Accept` header prefers, honoring `q` weights and
+    /// wildcards; without the header, JSON.
+    pub fn from_accept(accept: Option<&str>) -> Result<Format, Error> {
+        let Some(accept) = accept.filter(|accept| !accept.trim().is_empty()) else {
+            return Ok(Format::Json);
+        };
+        let ranges = accept
+            .split(',')
+            .filter_map(media_range)
+            .collect::<Vec<_>>();
+
+        let mut best = None;
+        for format in Format::ALL {
+            // The most specific range matching the format sets its weight.
+            let weight = format
+                .media_types()
+                .iter()
+                .filter_map(|media_type| {
+                    ranges
+                        .iter()
+                        .filter_map(|(range, q)| Some((specificity(range, media_type)?, *q)))
+                        .max_by_key(|(specificity, _)| *specificity)
+                })
+                .max_by(|(a, qa), (b, qb)| a.cmp(b).then(qa.total_cmp(qb)))
+                .map_or(0.0, |(_, q)| q);
+            if weight > 0.0 && best.is_none_or(|(_, best)| weight > best) {
+                best = Some((format, weight));
+            }
+        }
+        best.map(|(format, _)| format).ok_or(Error::NotAcceptable)
+    }
+
+    /// The format of a request body; without a `Content-Type`, JSON.
+    pub fn from_content_type(content_type: Option<&str>) -> Result<Format, Error> {
+        let Some(content_type) = content_type else {
+            return Ok(Format::Json);
+        };
+        let media_type = essence(content_type);
+        Format::ALL
+            .into_iter()
+            .find(|format| format.media_types().contains(&media_type.as_str()))
+            .ok_or(Error::UnsupportedMediaType)
+    }
+}
+
+/// A media type without its parameters, lowercased.
+fn essence(media_type: &str) -> String {
+    media_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// Splits an `Accept` or `Accept-Encoding` entry into its lowercased value
+/// and `q` weight.
+pub fn media_range(entry: &str) -> Option<(String, f32)> {
+    let range = essence(entry);
+    if range.is_empty() {
+        return None;
+    }
+    let q = entry
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.trim().strip_prefix("q="))
+        .next()
+        .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+    Some((range, q.clamp(0.0, 1.0)))
+}
+
+/// How specifically `range` matches `media_type`: 2 exactly, 1 as `type/*`,
+/// 0 as `*/*`.
+fn specificity(range: &str, media_type: &str) -> Option<u8> {
+    if range == media_type {
+        Some(2)
+    } else if range == "*/*" {
+        Some(0)
+    } else {
+        let kind = range.strip_suffix("/*")?;
+        media_type
+            .strip_prefix(kind)
+            .is_some_and(|rest| rest.starts_with('/'))
+            .then_some(1)
+    }
+}
+
+/// The format named by the request's `Accept` header, rejecting with
+/// `406 Not Acceptable` when the server offers none of it.
+pub fn accept() -> impl Filter<Extract = (Format,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("accept").and_then(|accept: Option<String>| async move {
+        Format::from_accept(accept.as_deref()).map_err(warp::reject::custom)
+    })
+}
+
+/// Decodes a request body in any supported format, by its `Content-Type`,
+/// after undoing any gzip `Content-Encoding` of up to `max_bytes`.
+///
+/// `list_fields` names the fields CSV carries as `;`-separated lists.
+pub fn body<T: DeserializeOwned + Send>(
+    list_fields: &'static [&'static str],
+    max_bytes: u64,
+) -> impl Filter<Extract = (T,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("content-type")
+        .and(warp::header::optional::<String>("content-encoding"))
+        .and(warp::body::bytes())
+        .and_then(
+            move |content_type: Option<String>,
+                  content_encoding: Option<String>,
+                  body: Bytes| async move {
+                compression::decode_request(content_encoding.as_deref(), &body, max_bytes)
+                    .and_then(|body| decode(content_type.as_deref(), &body, list_fields))
+                    .map_err(warp::reject::custom)
+            },
+        )
+}
+
+pub fn decode<T: DeserializeOwned>(
+    content_type: Option<&str>,
+    body: &[u8],
+    list_fields: &[&str],
+) -> Result<T, Error> {
+    let format = Format::from_content_type(content_type)?;
+    // A single record in JSON Lines is plain JSON.
+    if matches!(format, Format::Json | Format::NdJson) {
+        return serde_json::from_slice(body).map_err(|_| Error::InvalidData);
+    }
+    let value = match format {
+        Format::MsgPack => msgpack::from_slice(body),
+        text => {
+            let body = std::str::from_utf8(body).map_err(|_| Error::InvalidData)?;
+            match text {
+                Format::Csv => csv::from_str(body, list_fields),
+                Format::Xml => xml::from_str(body),
+                _ => yaml::from_str(body),
+            }
+        }
+    };
+    value
+        .and_then(|value| serde_json::from_value(value).map_err(|e| e.to_string()))
+        .map_err(|_| Error::InvalidData)
+}
+
+/// Renders `value`, trimmed to `fieldset`, in `format`.
+///
+/// CSV and XML write fields in `columns` order; XML names each record
+/// `<{element}>`.
+pub fn reply<T: Serialize>(
+    format: Format,
+    value: &T,
+    fieldset: &Fieldset,
+    element: &str,
+    columns: &[&str],
+) -> Response {
+    let mut response = if format == Format::Json && fieldset.is_all() {
+        // Serializing directly keeps the fields in declaration order.
+        warp::reply::json(value).into_response()
+    } else {
+        let value = fieldset.apply(value);
+        let body = match format {
+            Format::Json => serde_json::to_vec(&value).expect("values serialize"),
+            Format::Csv => csv::to_string(&value, columns).into_bytes(),
+            Format::Xml => xml::to_string(&value, element, columns).into_bytes(),
+            Format::Yaml => yaml::to_string(&value).into_bytes(),
+            Format::MsgPack => msgpack::to_vec(&value),
+            Format::NdJson => json_lines(&value),
+        };
+        let mut response = Response::new(body.into());
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(format.content_type()),
+        );
+        response
+    };
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
+    response
+}
+
+/// Writes each item of an array, or a single value, as a line of JSON.
+fn json_lines(value: &Value) -> Vec<u8> {
+    let items = match value {
+        Value::Array(items) => items.iter().collect(),
+        value => vec![value],
+    };
+    let mut out = Vec::new();
+    for item in items {
+        serde_json::to_writer(&mut out, item).expect("values serialize");
+        out.push(b'\n');
+    }
+    out
+}
+
+/// Lists every format next to JSON in the OpenAPI document, for the bodies
+/// of book requests and successful book reads.
+pub struct Documented;
+
+impl utoipa::Modify for Documented {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            // Imports and exports document their own media types.
+            if !path.contains("/books") || path.ends_with("/import") || path.ends_with("/export") {
+                continue;
+            }
+            for operation in item.operations.values_mut() {
+                if let Some(body) = operation.request_body.as_mut() {
+                    let others = alternatives(body.content.get("application/json"));
+                    body.content.extend(others);
+                }
+                if let Some(RefOr::T(ok)) = operation.responses.responses.get_mut("200") {
+                    let others = alternatives(ok.content.get("application/json"));
+                    ok.content.extend(others);
+                }
+            }
+        }
+    }
+}
+
+/// The JSON `content` entry repeated for every other format.
+fn alternatives(json: Option<&Content>) -> Vec<(String, Content)> {
+    json.map(|json| {
+        Format::ALL[1..]
+            .iter()
+            .map(|format| (format.media_types()[0].to_string(), json.clone()))
+            .collect()
+    })
+    .unwrap_or_default()
+}
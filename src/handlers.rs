@@ -1,117 +1,909 @@
 use crate::db;
 use crate::errors::Error;
-use crate::models::NewBook;
+use crate::export::{self, ExportQuery};
+use crate::fields::FieldsQuery;
+use crate::formats::{self, Format};
+use crate::health;
+use crate::idempotency::{self, IdempotentRequest, Outcome};
+use crate::import::{self, ImportQuery};
+use crate::metrics;
+use crate::models::{Book, HealthStatus, NewBook, Probe};
+use crate::repository::BookRepository;
+use crate::shutdown::Shutdown;
+use crate::streaming;
 use std::sync::Arc;
+use warp::http::{header, StatusCode};
+use warp::path::FullPath;
+use warp::reply::Response;
 use warp::{Rejection, Reply};
 
+/// Methods supported on `/v1/books`, as sent in `Allow` headers.
+pub const BOOKS_ALLOW: &str = "GET, HEAD, POST, OPTIONS";
+/// Methods supported on `/v1/books/{id}`, as sent in `Allow` headers.
+pub const BOOK_ALLOW: &str = "GET, HEAD, PUT, DELETE, OPTIONS";
+/// Methods supported on `/v1/books/import`, as sent in `Allow` headers.
+pub const IMPORT_ALLOW: &str = "POST, OPTIONS";
+/// Methods supported on `/v1/books/export`, as sent in `Allow` headers.
+pub const EXPORT_ALLOW: &str = "GET, HEAD, OPTIONS";
+
 #[utoipa::path(
     get,
-    path = "/books",
+    path = "/v1/books",
+    params(FieldsQuery),
+    responses(
+        (status = 200, description = "List of all books, with only the requested fields if `fields` is given", body = Vec<Book>),
+        (status = 400, description = "Unknown name in `fields` or `expand`", body = ErrorBody),
+        (status = 406, description = "None of the `Accept`ed types is offered", body = ErrorBody),
+        (status = 500, description = "Internal server error", body = ErrorBody),
+        (status = 503, description = "Database busy, retry after the Retry-After delay", body = ErrorBody,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+    ),
+    tag = "Books"
+)]
+pub async fn list_books<R: BookRepository>(
+    query: FieldsQuery,
+    format: Format,
+    repo: Arc<R>,
+) -> Result<impl Reply, Rejection> {
+    let fieldset = query
+        .parse(Book::FIELDS, Book::RELATIONS)
+        .map_err(warp::reject::custom)?;
+    streaming::books(repo, format, fieldset, Book::FIELDS, |book| book)
+        .await
+        .map_err(warp::reject::custom)
+}
+
+#[utoipa::path(
+    head,
+    path = "/v1/books",
+    params(FieldsQuery),
+    responses(
+        (status = 200, description = "The headers `GET /v1/books` would send, without the body"),
+        (status = 400, description = "Unknown name in `fields` or `expand`"),
+        (status = 406, description = "None of the `Accept`ed types is offered"),
+        (status = 500, description = "Internal server error"),
+        (status = 503, description = "Database busy, retry after the Retry-After delay",
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+    ),
+    tag = "Books"
+)]
+pub async fn head_books<R: BookRepository>(
+    query: FieldsQuery,
+    format: Format,
+    repo: Arc<R>,
+) -> Result<impl Reply, Rejection> {
+    // The server drops the body of HEAD responses but keeps their length.
+    list_books(query, format, repo).await
+}
+
+#[utoipa::path(
+    options,
+    path = "/v1/books",
     responses(
-        (status = 200, description = "List of all books", body = Vec<Book>),
-        (status = 500, description = "Internal server error")
+        (status = 204, description = "Methods supported on `/v1/books`",
+            headers(("Allow" = String, description = "GET, HEAD, POST, OPTIONS")))
     ),
     tag = "Books"
 )]
-pub async fn list_books(db: Arc<db::DbPool>) -> Result<impl Reply, Rejection> {
-    db::get_all_books(&db)
-        .map(|books| warp::reply::json(&books))
-        .map_err(|e| warp::reject::custom(Error::DatabaseError(e)))
+pub async fn books_options() -> Result<impl Reply, Rejection> {
+    Ok(allow(BOOKS_ALLOW))
 }
 
 #[utoipa::path(
     post,
-    path = "/books",
+    path = "/v1/books",
     request_body = NewBook,
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response when a request is retried with the same key")
+    ),
     responses(
-        (status = 200, description = "Book created successfully", body = Book),
-        (status = 400, description = "Invalid book data"),
-        (status = 500, description = "Internal server error")
+        (status = 201, description = "Book created successfully", body = Book,
+            headers(
+                ("Location" = String, description = "Path of the new book, e.g. /v1/books/1"),
+                ("Idempotent-Replayed" = bool, description = "Present when the response is replayed for a retried Idempotency-Key")
+            )),
+        (status = 400, description = "Invalid book data or malformed JSON", body = ErrorBody),
+        (status = 411, description = "Request body without a Content-Length", body = ErrorBody),
+        (status = 413, description = "Request body larger than the configured limit", body = ErrorBody),
+        (status = 415, description = "Request body type or encoding not supported", body = ErrorBody),
+        (status = 422, description = "Idempotency key reused with a different request", body = ErrorBody),
+        (status = 500, description = "Internal server error", body = ErrorBody),
+        (status = 503, description = "Database busy, retry after the Retry-After delay", body = ErrorBody,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
     ),
     tag = "Books"
 )]
-pub async fn create_book(new_book: NewBook, db: Arc<db::DbPool>) -> Result<impl Reply, Rejection> {
-    if new_book.title.is_empty() || new_book.author.is_empty() {
+pub async fn create_book<R: BookRepository>(
+    idempotency_key: Option<String>,
+    new_book: NewBook,
+    path: FullPath,
+    repo: Arc<R>,
+) -> Result<impl Reply, Rejection> {
+    if new_book.validate().is_err() {
         return Err(warp::reject::custom(Error::InvalidData));
     }
 
-    db::create_book(&db, new_book)
-        .map(|book| warp::reply::json(&book))
-        .map_err(|e| warp::reject::custom(Error::DatabaseError(e)))
+    let status = StatusCode::CREATED;
+    let request = idempotency_key.map(|key| IdempotentRequest {
+        key,
+        fingerprint: idempotency::fingerprint("POST", "/books", &new_book),
+        status,
+    });
+    repo.create(new_book, request)
+        .await
+        .map(|outcome| {
+            let id = created_id(&outcome);
+            with_location(idempotency::reply(outcome, status), &path, id)
+        })
+        .map_err(warp::reject::custom)
+}
+
+/// The id of a created book, also for a replay of the recorded response.
+fn created_id(outcome: &Outcome<Book>) -> Option<i32> {
+    match outcome {
+        Outcome::Fresh(book) => Some(book.id),
+        Outcome::Replayed(stored) => serde_json::from_str::<Book>(&stored.body)
+            .ok()
+            .map(|book| book.id),
+    }
+}
+
+/// Points `Location` at book `id` under the collection at `path`.
+fn with_location(mut response: Response, path: &FullPath, id: Option<i32>) -> Response {
+    if let Some(id) = id {
+        response.headers_mut().insert(
+            header::LOCATION,
+            format!("{}/{}", path.as_str().trim_end_matches('/'), id)
+                .parse()
+                .expect("book paths are valid headers"),
+        );
+    }
+    response
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/books/import",
+    params(ImportQuery),
+    request_body(content = String, content_type = "text/csv",
+        description = "Books as CSV with a header row, or one JSON book per line with `Content-Type: application/x-ndjson`"),
+    responses(
+        (status = 200, description = "What became of each row; rows that fail do not stop the others", body = ImportReport),
+        (status = 400, description = "Unreadable body, such as a CSV header with a duplicate column, or invalid `dry_run`", body = ErrorBody),
+        (status = 411, description = "Request body without a Content-Length", body = ErrorBody),
+        (status = 413, description = "Request body larger than the configured import limit", body = ErrorBody),
+        (status = 415, description = "Request body type or encoding not supported", body = ErrorBody),
+        (status = 500, description = "Internal server error", body = ErrorBody)
+    ),
+    tag = "Books"
+)]
+pub async fn import_books<R: BookRepository>(
+    query: ImportQuery,
+    format: Format,
+    body: Vec<u8>,
+    repo: Arc<R>,
+) -> Result<impl Reply, Rejection> {
+    import::books(repo, format, &body, &[], query.dry_run, NewBook::validate)
+        .await
+        .map(|report| warp::reply::json(&report))
+        .map_err(warp::reject::custom)
+}
+
+#[utoipa::path(
+    options,
+    path = "/v1/books/import",
+    responses(
+        (status = 204, description = "Methods supported on `/v1/books/import`",
+            headers(("Allow" = String, description = "POST, OPTIONS")))
+    ),
+    tag = "Books"
+)]
+pub async fn import_options() -> Result<impl Reply, Rejection> {
+    Ok(allow(IMPORT_ALLOW))
 }
 
 #[utoipa::path(
     get,
-    path = "/books/{id}",
+    path = "/v1/books/export",
+    params(ExportQuery, FieldsQuery),
     responses(
-        (status = 200, description = "Book found", body = Book),
+        (status = 200, description = "Every book as a file download, with only the requested fields if `fields` is given. CSV starts with a header row and keeps the columns in schema order",
+            content(("application/json" = Vec<Book>), ("application/x-ndjson" = String), ("text/csv" = String)),
+            headers(("Content-Disposition" = String, description = "attachment; filename=\"books-2026-10-18.csv\""))),
+        (status = 400, description = "Unknown `format`, or unknown name in `fields` or `expand`", body = ErrorBody),
+        (status = 500, description = "Internal server error", body = ErrorBody),
+        (status = 503, description = "Database busy, retry after the Retry-After delay", body = ErrorBody,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+    ),
+    tag = "Books"
+)]
+pub async fn export_books<R: BookRepository>(
+    export: ExportQuery,
+    query: FieldsQuery,
+    repo: Arc<R>,
+) -> Result<impl Reply, Rejection> {
+    let fieldset = query
+        .parse(Book::FIELDS, Book::RELATIONS)
+        .map_err(warp::reject::custom)?;
+    streaming::books(
+        repo,
+        export.format.format(),
+        fieldset,
+        Book::FIELDS,
+        |book| book,
+    )
+    .await
+    .map(|response| export::attachment(response, export.format))
+    .map_err(warp::reject::custom)
+}
+
+#[utoipa::path(
+    head,
+    path = "/v1/books/export",
+    params(ExportQuery, FieldsQuery),
+    responses(
+        (status = 200, description = "The headers `GET /v1/books/export` would send, without the body"),
+        (status = 400, description = "Unknown `format`, or unknown name in `fields` or `expand`")
+    ),
+    tag = "Books"
+)]
+pub async fn head_export(export: ExportQuery, query: FieldsQuery) -> Result<impl Reply, Rejection> {
+    // Only the headers are built; no books are read.
+    query
+        .parse(Book::FIELDS, Book::RELATIONS)
+        .map_err(warp::reject::custom)?;
+    Ok(export::head(export.format))
+}
+
+#[utoipa::path(
+    options,
+    path = "/v1/books/export",
+    responses(
+        (status = 204, description = "Methods supported on `/v1/books/export`",
+            headers(("Allow" = String, description = "GET, HEAD, OPTIONS")))
+    ),
+    tag = "Books"
+)]
+pub async fn export_options() -> Result<impl Reply, Rejection> {
+    Ok(allow(EXPORT_ALLOW))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/books/{id}",
+    responses(
+        (status = 200, description = "Book found, with only the requested fields if `fields` is given", body = Book),
+        (status = 400, description = "Unknown name in `fields` or `expand`", body = ErrorBody),
+        (status = 406, description = "None of the `Accept`ed types is offered", body = ErrorBody),
+        (status = 404, description = "Book not found", body = ErrorBody),
+        (status = 500, description = "Internal server error", body = ErrorBody),
+        (status = 503, description = "Database busy, retry after the Retry-After delay", body = ErrorBody,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+    ),
+    params(
+        ("id" = i32, Path, description = "Book id"),
+        FieldsQuery
+    ),
+    tag = "Books"
+)]
+pub async fn get_book<R: BookRepository>(
+    id: i32,
+    query: FieldsQuery,
+    format: Format,
+    repo: Arc<R>,
+) -> Result<impl Reply, Rejection> {
+    let fieldset = query
+        .parse(Book::FIELDS, Book::RELATIONS)
+        .map_err(warp::reject::custom)?;
+    repo.get(id)
+        .await
+        .map(|book| formats::reply(format, &book, &fieldset, "book", Book::FIELDS))
+        .map_err(warp::reject::custom)
+}
+
+#[utoipa::path(
+    head,
+    path = "/v1/books/{id}",
+    responses(
+        (status = 200, description = "The headers `GET /v1/books/{id}` would send, without the body"),
+        (status = 400, description = "Unknown name in `fields` or `expand`"),
+        (status = 406, description = "None of the `Accept`ed types is offered"),
         (status = 404, description = "Book not found"),
-        (status = 500, description = "Internal server error")
+        (status = 500, description = "Internal server error"),
+        (status = 503, description = "Database busy, retry after the Retry-After delay",
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+    ),
+    params(
+        ("id" = i32, Path, description = "Book id"),
+        FieldsQuery
+    ),
+    tag = "Books"
+)]
+pub async fn head_book<R: BookRepository>(
+    id: i32,
+    query: FieldsQuery,
+    format: Format,
+    repo: Arc<R>,
+) -> Result<impl Reply, Rejection> {
+    get_book(id, query, format, repo).await
+}
+
+#[utoipa::path(
+    options,
+    path = "/v1/books/{id}",
+    responses(
+        (status = 204, description = "Methods supported on `/v1/books/{id}`",
+            headers(("Allow" = String, description = "GET, HEAD, PUT, DELETE, OPTIONS")))
     ),
     params(
         ("id" = i32, Path, description = "Book id")
     ),
     tag = "Books"
 )]
-pub async fn get_book(id: i32, db: Arc<db::DbPool>) -> Result<impl Reply, Rejection> {
-    match db::get_book(&db, id) {
-        Ok(book) => Ok(warp::reply::json(&book)),
-        Err(diesel::result::Error::NotFound) => Err(warp::reject::custom(Error::NotFound)),
-        Err(e) => Err(warp::reject::custom(Error::DatabaseError(e))),
-    }
+pub async fn book_options(_id: i32) -> Result<impl Reply, Rejection> {
+    Ok(allow(BOOK_ALLOW))
+}
+
+fn allow(methods: &'static str) -> impl Reply {
+    warp::reply::with_header(
+        warp::reply::with_status(warp::reply(), StatusCode::NO_CONTENT),
+        header::ALLOW,
+        methods,
+    )
 }
 
 #[utoipa::path(
     put,
-    path = "/books/{id}",
+    path = "/v1/books/{id}",
     request_body = NewBook,
     responses(
         (status = 200, description = "Book updated successfully", body = Book),
-        (status = 400, description = "Invalid book data"),
-        (status = 404, description = "Book not found"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid book data or malformed JSON", body = ErrorBody),
+        (status = 404, description = "Book not found", body = ErrorBody),
+        (status = 411, description = "Request body without a Content-Length", body = ErrorBody),
+        (status = 413, description = "Request body larger than the configured limit", body = ErrorBody),
+        (status = 415, description = "Request body type or encoding not supported", body = ErrorBody),
+        (status = 500, description = "Internal server error", body = ErrorBody),
+        (status = 503, description = "Database busy, retry after the Retry-After delay", body = ErrorBody,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
     ),
     params(
         ("id" = i32, Path, description = "Book id")
     ),
     tag = "Books"
 )]
-pub async fn update_book(
+pub async fn update_book<R: BookRepository>(
     id: i32,
     updated_book: NewBook,
-    db: Arc<db::DbPool>,
+    repo: Arc<R>,
 ) -> Result<impl Reply, Rejection> {
-    if updated_book.title.is_empty() || updated_book.author.is_empty() {
+    if updated_book.validate().is_err() {
         return Err(warp::reject::custom(Error::InvalidData));
     }
 
-    match db::update_book(&db, id, updated_book) {
-        Ok(book) => Ok(warp::reply::json(&book)),
-        Err(diesel::result::Error::NotFound) => Err(warp::reject::custom(Error::NotFound)),
-        Err(e) => Err(warp::reject::custom(Error::DatabaseError(e))),
-    }
+    repo.update(id, updated_book)
+        .await
+        .map(|book| warp::reply::json(&book))
+        .map_err(warp::reject::custom)
 }
 
 #[utoipa::path(
     delete,
-    path = "/books/{id}",
+    path = "/v1/books/{id}",
     responses(
         (status = 204, description = "Book deleted successfully"),
-        (status = 404, description = "Book not found"),
-        (status = 500, description = "Internal server error")
+        (status = 404, description = "Book not found", body = ErrorBody),
+        (status = 500, description = "Internal server error", body = ErrorBody),
+        (status = 503, description = "Database busy, retry after the Retry-After delay", body = ErrorBody,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
     ),
     params(
         ("id" = i32, Path, description = "Book id")
     ),
     tag = "Books"
 )]
-pub async fn delete_book(id: i32, db: Arc<db::DbPool>) -> Result<impl Reply, Rejection> {
-    match db::delete_book(&db, id) {
-        Ok(_) => Ok(warp::reply::with_status(
-            "Book deleted",
-            warp::http::StatusCode::NO_CONTENT,
-        )),
-        Err(diesel::result::Error::NotFound) => Err(warp::reject::custom(Error::NotFound)),
-        Err(e) => Err(warp::reject::custom(Error::DatabaseError(e))),
+pub async fn delete_book<R: BookRepository>(
+    id: i32,
+    repo: Arc<R>,
+) -> Result<impl Reply, Rejection> {
+    repo.delete(id)
+        .await
+        .map(|_| warp::reply::with_status(warp::reply(), StatusCode::NO_CONTENT))
+        .map_err(warp::reject::custom)
+}
+
+#[utoipa::path(
+    get,
+    path = "/diagnostics/db",
+    responses(
+        (status = 200, description = "Connection settings in effect and pool status", body = DbDiagnostics),
+        (status = 500, description = "Internal server error", body = ErrorBody),
+        (status = 503, description = "Database busy, retry after the Retry-After delay", body = ErrorBody,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+    ),
+    tag = "Diagnostics"
+)]
+pub async fn db_diagnostics(db: Arc<db::DbPool>) -> Result<impl Reply, Rejection> {
+    let pool = db.status();
+
+    db.run(move |conn| db::get_diagnostics(conn, pool))
+        .await
+        .map(|diagnostics| warp::reply::json(&diagnostics))
+        .map_err(warp::reject::custom)
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    responses(
+        (status = 200, description = "The process is alive", body = Probe)
+    ),
+    tag = "Health"
+)]
+pub async fn healthz() -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&Probe {
+        status: HealthStatus::Up,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, description = "Ready to serve traffic", body = Probe),
+        (status = 503, description = "A dependency is down or the server is shutting down", body = Probe)
+    ),
+    tag = "Health"
+)]
+pub async fn readyz(db: Arc<db::DbPool>, shutdown: Arc<Shutdown>) -> Result<impl Reply, Rejection> {
+    let status = health::check(&db, &shutdown).await.status;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&Probe { status }),
+        health_status_code(status),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses(
+        (status = 200, description = "Every component is up", body = HealthReport),
+        (status = 503, description = "At least one component is down", body = HealthReport)
+    ),
+    tag = "Health"
+)]
+pub async fn health(db: Arc<db::DbPool>, shutdown: Arc<Shutdown>) -> Result<impl Reply, Rejection> {
+    let report = health::check(&db, &shutdown).await;
+    let code = health_status_code(report.status);
+    Ok(warp::reply::with_status(warp::reply::json(&report), code))
+}
+
+fn health_status_code(status: HealthStatus) -> StatusCode {
+    match status {
+        HealthStatus::Up => StatusCode::OK,
+        HealthStatus::Down => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text exposition format", body = String, content_type = "text/plain")
+    ),
+    tag = "Diagnostics"
+)]
+pub async fn metrics<R: BookRepository>(
+    repo: Arc<R>,
+    db: Arc<db::DbPool>,
+) -> Result<impl Reply, Rejection> {
+    metrics::METRICS.set_pool_status(&db.status());
+    // A failed count leaves the last known value in place rather than failing the scrape.
+    if let Ok(count) = repo.count().await {
+        metrics::METRICS.set_book_count(count);
+    }
+
+    Ok(warp::reply::with_header(
+        metrics::render(),
+        "content-type",
+        "text/plain; version=0.0.4",
+    ))
+}
+
+/// Handlers for `/v2`, which serve [`v2::Book`] over the same storage.
+///
+/// [`v2::Book`]: crate::models::v2::Book
+pub mod v2 {
+    use super::{
+        allow, created_id, with_location, BOOKS_ALLOW, BOOK_ALLOW, EXPORT_ALLOW, IMPORT_ALLOW,
+    };
+    use crate::errors::Error;
+    use crate::export::{self, ExportQuery};
+    use crate::fields::FieldsQuery;
+    use crate::formats::{self, Format};
+    use crate::idempotency::{self, IdempotentRequest, Outcome};
+    use crate::import::{self, ImportQuery};
+    use crate::models;
+    use crate::models::v2::{Book, NewBook};
+    use crate::repository::BookRepository;
+    use crate::streaming;
+    use std::sync::Arc;
+    use warp::http::StatusCode;
+    use warp::path::FullPath;
+    use warp::{Rejection, Reply};
+
+    #[utoipa::path(
+        get,
+        path = "/v2/books",
+        params(FieldsQuery),
+        responses(
+            (status = 200, description = "List of all books, with only the requested fields if `fields` is given", body = Vec<Book>),
+            (status = 400, description = "Unknown name in `fields` or `expand`", body = ErrorBody),
+            (status = 406, description = "None of the `Accept`ed types is offered", body = ErrorBody),
+            (status = 500, description = "Internal server error", body = ErrorBody),
+            (status = 503, description = "Database busy, retry after the Retry-After delay", body = ErrorBody,
+                headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+        ),
+        tag = "Books"
+    )]
+    pub async fn list_books<R: BookRepository>(
+        query: FieldsQuery,
+        format: Format,
+        repo: Arc<R>,
+    ) -> Result<impl Reply, Rejection> {
+        let fieldset = query
+            .parse(Book::FIELDS, Book::RELATIONS)
+            .map_err(warp::reject::custom)?;
+        streaming::books(repo, format, fieldset, Book::FIELDS, Book::from)
+            .await
+            .map_err(warp::reject::custom)
+    }
+
+    #[utoipa::path(
+        head,
+        path = "/v2/books",
+        params(FieldsQuery),
+        responses(
+            (status = 200, description = "The headers `GET /v2/books` would send, without the body"),
+            (status = 400, description = "Unknown name in `fields` or `expand`"),
+            (status = 406, description = "None of the `Accept`ed types is offered"),
+            (status = 500, description = "Internal server error"),
+            (status = 503, description = "Database busy, retry after the Retry-After delay",
+                headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+        ),
+        tag = "Books"
+    )]
+    pub async fn head_books<R: BookRepository>(
+        query: FieldsQuery,
+        format: Format,
+        repo: Arc<R>,
+    ) -> Result<impl Reply, Rejection> {
+        list_books(query, format, repo).await
+    }
+
+    #[utoipa::path(
+        options,
+        path = "/v2/books",
+        responses(
+            (status = 204, description = "Methods supported on `/v2/books`",
+                headers(("Allow" = String, description = "GET, HEAD, POST, OPTIONS")))
+        ),
+        tag = "Books"
+    )]
+    pub async fn books_options() -> Result<impl Reply, Rejection> {
+        Ok(allow(BOOKS_ALLOW))
+    }
+
+    #[utoipa::path(
+        post,
+        path = "/v2/books",
+        request_body = NewBook,
+        params(
+            ("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response when a request is retried with the same key")
+        ),
+        responses(
+            (status = 201, description = "Book created successfully", body = Book,
+                headers(
+                    ("Location" = String, description = "Path of the new book, e.g. /v2/books/1"),
+                    ("Idempotent-Replayed" = bool, description = "Present when the response is replayed for a retried Idempotency-Key")
+                )),
+            (status = 400, description = "Invalid book data or malformed JSON", body = ErrorBody),
+            (status = 411, description = "Request body without a Content-Length", body = ErrorBody),
+            (status = 413, description = "Request body larger than the configured limit", body = ErrorBody),
+            (status = 415, description = "Request body type or encoding not supported", body = ErrorBody),
+            (status = 422, description = "Idempotency key reused with a different request", body = ErrorBody),
+            (status = 500, description = "Internal server error", body = ErrorBody),
+            (status = 503, description = "Database busy, retry after the Retry-After delay", body = ErrorBody,
+                headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+        ),
+        tag = "Books"
+    )]
+    pub async fn create_book<R: BookRepository>(
+        idempotency_key: Option<String>,
+        new_book: NewBook,
+        path: FullPath,
+        repo: Arc<R>,
+    ) -> Result<impl Reply, Rejection> {
+        if new_book.validate().is_err() {
+            return Err(warp::reject::custom(Error::InvalidData));
+        }
+
+        let status = StatusCode::CREATED;
+        let request = idempotency_key.map(|key| IdempotentRequest {
+            key,
+            fingerprint: idempotency::fingerprint("POST", "/v2/books", &new_book),
+            status,
+        });
+        repo.create(new_book.into(), request)
+            .await
+            .map(|outcome| {
+                let id = created_id(&outcome);
+                with_location(idempotency::reply(to_v2(outcome), status), &path, id)
+            })
+            .map_err(warp::reject::custom)
+    }
+
+    /// Converts a create outcome, including a response recorded in the storage shape.
+    fn to_v2(outcome: Outcome<models::Book>) -> Outcome<Book> {
+        match outcome {
+            Outcome::Fresh(book) => Outcome::Fresh(book.into()),
+            Outcome::Replayed(mut stored) => {
+                if let Ok(book) = serde_json::from_str::<models::Book>(&stored.body) {
+                    stored.body =
+                        serde_json::to_string(&Book::from(book)).expect("books serialize");
+                }
+                Outcome::Replayed(stored)
+            }
+        }
+    }
+
+    #[utoipa::path(
+        post,
+        path = "/v2/books/import",
+        params(ImportQuery),
+        request_body(content = String, content_type = "text/csv",
+            description = "Books as CSV with a header row, authors separated by `;`, or one JSON book per line with `Content-Type: application/x-ndjson`"),
+        responses(
+            (status = 200, description = "What became of each row; rows that fail do not stop the others", body = ImportReport),
+            (status = 400, description = "Unreadable body, such as a CSV header with a duplicate column, or invalid `dry_run`", body = ErrorBody),
+            (status = 411, description = "Request body without a Content-Length", body = ErrorBody),
+            (status = 413, description = "Request body larger than the configured import limit", body = ErrorBody),
+            (status = 415, description = "Request body type or encoding not supported", body = ErrorBody),
+            (status = 500, description = "Internal server error", body = ErrorBody)
+        ),
+        tag = "Books"
+    )]
+    pub async fn import_books<R: BookRepository>(
+        query: ImportQuery,
+        format: Format,
+        body: Vec<u8>,
+        repo: Arc<R>,
+    ) -> Result<impl Reply, Rejection> {
+        import::books(
+            repo,
+            format,
+            &body,
+            NewBook::LIST_FIELDS,
+            query.dry_run,
+            NewBook::validate,
+        )
+        .await
+        .map(|report| warp::reply::json(&report))
+        .map_err(warp::reject::custom)
+    }
+
+    #[utoipa::path(
+        options,
+        path = "/v2/books/import",
+        responses(
+            (status = 204, description = "Methods supported on `/v2/books/import`",
+                headers(("Allow" = String, description = "POST, OPTIONS")))
+        ),
+        tag = "Books"
+    )]
+    pub async fn import_options() -> Result<impl Reply, Rejection> {
+        Ok(allow(IMPORT_ALLOW))
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v2/books/export",
+        params(ExportQuery, FieldsQuery),
+        responses(
+            (status = 200, description = "Every book as a file download, with only the requested fields if `fields` is given. CSV starts with a header row and keeps the columns in schema order, with authors separated by `; `",
+                content(("application/json" = Vec<Book>), ("application/x-ndjson" = String), ("text/csv" = String)),
+                headers(("Content-Disposition" = String, description = "attachment; filename=\"books-2026-10-18.csv\""))),
+            (status = 400, description = "Unknown `format`, or unknown name in `fields` or `expand`", body = ErrorBody),
+            (status = 500, description = "Internal server error", body = ErrorBody),
+            (status = 503, description = "Database busy, retry after the Retry-After delay", body = ErrorBody,
+                headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+        ),
+        tag = "Books"
+    )]
+    pub async fn export_books<R: BookRepository>(
+        export: ExportQuery,
+        query: FieldsQuery,
+        repo: Arc<R>,
+    ) -> Result<impl Reply, Rejection> {
+        let fieldset = query
+            .parse(Book::FIELDS, Book::RELATIONS)
+            .map_err(warp::reject::custom)?;
+        streaming::books(
+            repo,
+            export.format.format(),
+            fieldset,
+            Book::FIELDS,
+            Book::from,
+        )
+        .await
+        .map(|response| export::attachment(response, export.format))
+        .map_err(warp::reject::custom)
+    }
+
+    #[utoipa::path(
+        head,
+        path = "/v2/books/export",
+        params(ExportQuery, FieldsQuery),
+        responses(
+            (status = 200, description = "The headers `GET /v2/books/export` would send, without the body"),
+            (status = 400, description = "Unknown `format`, or unknown name in `fields` or `expand`")
+        ),
+        tag = "Books"
+    )]
+    pub async fn head_export(
+        export: ExportQuery,
+        query: FieldsQuery,
+    ) -> Result<impl Reply, Rejection> {
+        // Only the headers are built; no books are read.
+        query
+            .parse(Book::FIELDS, Book::RELATIONS)
+            .map_err(warp::reject::custom)?;
+        Ok(export::head(export.format))
+    }
+
+    #[utoipa::path(
+        options,
+        path = "/v2/books/export",
+        responses(
+            (status = 204, description = "Methods supported on `/v2/books/export`",
+                headers(("Allow" = String, description = "GET, HEAD, OPTIONS")))
+        ),
+        tag = "Books"
+    )]
+    pub async fn export_options() -> Result<impl Reply, Rejection> {
+        Ok(allow(EXPORT_ALLOW))
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v2/books/{id}",
+        responses(
+            (status = 200, description = "Book found, with only the requested fields if `fields` is given", body = Book),
+            (status = 400, description = "Unknown name in `fields` or `expand`", body = ErrorBody),
+            (status = 406, description = "None of the `Accept`ed types is offered", body = ErrorBody),
+            (status = 404, description = "Book not found", body = ErrorBody),
+            (status = 500, description = "Internal server error", body = ErrorBody),
+            (status = 503, description = "Database busy, retry after the Retry-After delay", body = ErrorBody,
+                headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+        ),
+        params(
+            ("id" = i32, Path, description = "Book id"),
+            FieldsQuery
+        ),
+        tag = "Books"
+    )]
+    pub async fn get_book<R: BookRepository>(
+        id: i32,
+        query: FieldsQuery,
+        format: Format,
+        repo: Arc<R>,
+    ) -> Result<impl Reply, Rejection> {
+        let fieldset = query
+            .parse(Book::FIELDS, Book::RELATIONS)
+            .map_err(warp::reject::custom)?;
+        repo.get(id)
+            .await
+            .map(|book| formats::reply(format, &Book::from(book), &fieldset, "book", Book::FIELDS))
+            .map_err(warp::reject::custom)
+    }
+
+    #[utoipa::path(
+        head,
+        path = "/v2/books/{id}",
+        responses(
+            (status = 200, description = "The headers `GET /v2/books/{id}` would send, without the body"),
+            (status = 400, description = "Unknown name in `fields` or `expand`"),
+            (status = 406, description = "None of the `Accept`ed types is offered"),
+            (status = 404, description = "Book not found"),
+            (status = 500, description = "Internal server error"),
+            (status = 503, description = "Database busy, retry after the Retry-After delay",
+                headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+        ),
+        params(
+            ("id" = i32, Path, description = "Book id"),
+            FieldsQuery
+        ),
+        tag = "Books"
+    )]
+    pub async fn head_book<R: BookRepository>(
+        id: i32,
+        query: FieldsQuery,
+        format: Format,
+        repo: Arc<R>,
+    ) -> Result<impl Reply, Rejection> {
+        get_book(id, query, format, repo).await
+    }
+
+    #[utoipa::path(
+        options,
+        path = "/v2/books/{id}",
+        responses(
+            (status = 204, description = "Methods supported on `/v2/books/{id}`",
+                headers(("Allow" = String, description = "GET, HEAD, PUT, DELETE, OPTIONS")))
+        ),
+        params(
+            ("id" = i32, Path, description = "Book id")
+        ),
+        tag = "Books"
+    )]
+    pub async fn book_options(_id: i32) -> Result<impl Reply, Rejection> {
+        Ok(allow(BOOK_ALLOW))
+    }
+
+    #[utoipa::path(
+        put,
+        path = "/v2/books/{id}",
+        request_body = NewBook,
+        responses(
+            (status = 200, description = "Book updated successfully", body = Book),
+            (status = 400, description = "Invalid book data or malformed JSON", body = ErrorBody),
+            (status = 404, description = "Book not found", body = ErrorBody),
+            (status = 411, description = "Request body without a Content-Length", body = ErrorBody),
+            (status = 413, description = "Request body larger than the configured limit", body = ErrorBody),
+            (status = 415, description = "Request body type or encoding not supported", body = ErrorBody),
+            (status = 500, description = "Internal server error", body = ErrorBody),
+            (status = 503, description = "Database busy, retry after the Retry-After delay", body = ErrorBody,
+                headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+        ),
+        params(
+            ("id" = i32, Path, description = "Book id")
+        ),
+        tag = "Books"
+    )]
+    pub async fn update_book<R: BookRepository>(
+        id: i32,
+        updated_book: NewBook,
+        repo: Arc<R>,
+    ) -> Result<impl Reply, Rejection> {
+        if updated_book.validate().is_err() {
+            return Err(warp::reject::custom(Error::InvalidData));
+        }
+
+        repo.update(id, updated_book.into())
+            .await
+            .map(|book| warp::reply::json(&Book::from(book)))
+            .map_err(warp::reject::custom)
+    }
+
+    #[utoipa::path(
+        delete,
+        path = "/v2/books/{id}",
+        responses(
+            (status = 204, description = "Book deleted successfully"),
+            (status = 404, description = "Book not found", body = ErrorBody),
+            (status = 500, description = "Internal server error", body = ErrorBody),
+            (status = 503, description = "Database busy, retry after the Retry-After delay", body = ErrorBody,
+                headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+        ),
+        params(
+            ("id" = i32, Path, description = "Book id")
+        ),
+        tag = "Books"
+    )]
+    pub async fn delete_book<R: BookRepository>(
+        id: i32,
+        repo: Arc<R>,
+    ) -> Result<impl Reply, Rejection> {
+        super::delete_book(id, repo).await
     }
 }
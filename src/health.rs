@@ -0,0 +1,79 @@
+use std::future::Future;
+use std::time::Instant;
+
+use crate::db::{self, DbPool};
+use crate::errors::Error;
+use crate::models::{ComponentHealth, HealthReport, HealthStatus};
+use crate::shutdown::Shutdown;
+
+/// Checks every dependency the API needs to serve traffic.
+///
+/// The server counts as down once shutdown has started, so load balancers
+/// stop routing to it while in-flight requests drain.
+pub async fn check(pool: &DbPool, shutdown: &Shutdown) -> HealthReport {
+    let mut components = vec![
+        timed("database", pool.run(db::ping), |_| None).await,
+        timed("migrations", pool.run(db::pending_migrations), |pending| {
+            (*pending > 0).then(|| format!("{} pending", pending))
+        })
+        .await,
+    ];
+
+    let pool_status = pool.status();
+    components.push(ComponentHealth {
+        name: "pool".to_string(),
+        status: HealthStatus::Up,
+        latency_ms: None,
+        detail: Some(format!(
+            "{}/{} connections idle, {} queued jobs",
+            pool_status.idle_connections, pool_status.connections, pool_status.queued_jobs
+        )),
+    });
+
+    let shutting_down = shutdown.is_triggered();
+    components.push(ComponentHealth {
+        name: "shutdown".to_string(),
+        status: if shutting_down {
+            HealthStatus::Down
+        } else {
+            HealthStatus::Up
+        },
+        latency_ms: None,
+        detail: shutting_down.then(|| "shutting down".to_string()),
+    });
+
+    let status = if components.iter().all(|c| c.status == HealthStatus::Up) {
+        HealthStatus::Up
+    } else {
+        HealthStatus::Down
+    };
+
+    HealthReport { status, components }
+}
+
+/// Times `check`; a non-`None` `problem` for a successful result marks the component down.
+async fn timed<T>(
+    name: &str,
+    check: impl Future<Output = Result<T, Error>>,
+    problem: impl FnOnce(&T) -> Option<String>,
+) -> ComponentHealth {
+    let started = Instant::now();
+    let result = check.await;
+    let latency_ms = Some(started.elapsed().as_secs_f64() * 1000.0);
+
+    let detail = match &result {
+        Ok(value) => problem(value),
+        Err(e) => Some(e.to_string()),
+    };
+
+    ComponentHealth {
+        name: name.to_string(),
+        status: if detail.is_none() {
+            HealthStatus::Up
+        } else {
+            HealthStatus::Down
+        },
+        latency_ms,
+        detail,
+    }
+}
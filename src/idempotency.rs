@@ -0,0 +1,233 @@
+use diesel::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use warp::http::{header, Response, StatusCode};
+use warp::{Filter, Rejection};
+
+use crate::db;
+use crate::errors::Error;
+use crate::schema::idempotency_keys;
+
+pub const HEADER: &str = "idempotency-key";
+
+/// How long a stored response is replayed before its key may be reused.
+pub const KEY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often expired keys are purged from the database.
+pub const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = idempotency_keys)]
+struct IdempotencyRecord {
+    key: String,
+    fingerprint: String,
+    status: i32,
+    body: String,
+    created_at: i64,
+}
+
+/// Extracts the optional `Idempotency-Key` request header.
+pub fn key() -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(HEADER)
+}
+
+/// Hashes the parts of a request that must match for a replay to be honored.
+pub fn fingerprint<T: Serialize>(method: &str, path: &str, body: &T) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_bytes());
+    hasher.update(b" ");
+    hasher.update(path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(serde_json::to_vec(body).unwrap_or_default());
+    hex::encode(hasher.finalize())
+}
+
+/// A request that must take effect at most once per key.
+#[derive(Clone)]
+pub struct IdempotentRequest {
+    pub key: String,
+    pub fingerprint: String,
+    /// Status of the response recorded when the request first succeeds.
+    pub status: StatusCode,
+}
+
+/// A response recorded for an idempotency key.
+#[derive(Clone)]
+pub struct StoredResponse {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+/// Result of an idempotent operation: a new value or the recorded response.
+pub enum Outcome<T> {
+    Fresh(T),
+    Replayed(StoredResponse),
+}
+
+/// Builds the JSON reply for an outcome, flagging replays with `Idempotent-Replayed`.
+pub fn reply<T: Serialize>(outcome: Outcome<T>, status: StatusCode) -> warp::reply::Response {
+    match outcome {
+        Outcome::Fresh(value) => json_response(status, serde_json::to_string(&value).unwrap()),
+        Outcome::Replayed(stored) => {
+            let mut response = json_response(stored.status, stored.body);
+            response.headers_mut().insert(
+                "idempotent-replayed",
+                header::HeaderValue::from_static("true"),
+            );
+            response
+        }
+    }
+}
+
+/// Returns whether a response recorded at `created_at` (Unix seconds) has expired.
+pub fn is_expired(created_at: i64) -> bool {
+    created_at <= expiry_cutoff()
+}
+
+/// Runs `operation` at most once per idempotency key.
+///
+/// Without a request the operation simply runs. Otherwise the key is claimed
+/// before the operation runs, and the claim, the operation and the stored
+/// response share one transaction. A concurrent retry waits on the claim and
+/// then replays the complete response.
+pub fn execute<T, F>(
+    conn: &mut db::DbConnection,
+    request: Option<&IdempotentRequest>,
+    operation: F,
+) -> Result<Outcome<T>, Error>
+where
+    T: Serialize,
+    F: FnOnce(&mut db::DbConnection) -> Result<T, diesel::result::Error>,
+{
+    use crate::schema::idempotency_keys::dsl;
+
+    let Some(request) = request else {
+        return Ok(Outcome::Fresh(operation(conn)?));
+    };
+
+    conn.transaction(|conn| {
+        if !claim(conn, request)? {
+            let record = dsl::idempotency_keys
+                .find(&request.key)
+                .first::<IdempotencyRecord>(conn)?;
+            if record.fingerprint != request.fingerprint {
+                return Err(Error::IdempotencyKeyReused);
+            }
+            return Ok(Outcome::Replayed(StoredResponse {
+                status: StatusCode::from_u16(record.status as u16).unwrap_or(request.status),
+                body: record.body,
+            }));
+        }
+
+        let value = operation(conn)?;
+        diesel::update(dsl::idempotency_keys.find(&request.key))
+            .set((
+                dsl::status.eq(request.status.as_u16() as i32),
+                dsl::body.eq(serde_json::to_string(&value).unwrap()),
+            ))
+            .execute(conn)?;
+
+        Ok(Outcome::Fresh(value))
+    })
+}
+
+/// Takes `request.key` for this transaction, unless an unexpired response is
+/// already recorded for it.
+///
+/// The insert is the transaction's first statement, so SQLite takes its write
+/// lock up front and Postgres waits for a concurrent claim of the same key to
+/// commit or roll back before reporting the conflict.
+fn claim(
+    conn: &mut db::DbConnection,
+    request: &IdempotentRequest,
+) -> Result<bool, diesel::result::Error> {
+    use crate::schema::idempotency_keys::dsl;
+
+    let record = IdempotencyRecord {
+        key: request.key.clone(),
+        fingerprint: request.fingerprint.clone(),
+        status: 0,
+        body: String::new(),
+        created_at: now(),
+    };
+    let insert = diesel::insert_into(dsl::idempotency_keys)
+        .values(&record)
+        .on_conflict_do_nothing();
+    // `ON CONFLICT` is built per backend.
+    let inserted = match conn {
+        #[cfg(feature = "sqlite")]
+        db::DbConnection::Sqlite(conn) => insert.execute(conn)?,
+        #[cfg(feature = "postgres")]
+        db::DbConnection::Postgres(conn) => insert.execute(conn)?,
+    };
+    if inserted == 1 {
+        return Ok(true);
+    }
+
+    // An expired key is taken over in place.
+    let renewed = diesel::update(
+        dsl::idempotency_keys
+            .find(&request.key)
+            .filter(dsl::created_at.le(expiry_cutoff())),
+    )
+    .set((
+        dsl::fingerprint.eq(&request.fingerprint),
+        dsl::created_at.eq(now()),
+    ))
+    .execute(conn)?;
+    Ok(renewed == 1)
+}
+
+/// Deletes keys older than [`KEY_TTL`], returning how many were removed.
+pub fn purge_expired(conn: &mut db::DbConnection) -> Result<usize, diesel::result::Error> {
+    use crate::schema::idempotency_keys::dsl::*;
+
+    diesel::delete(idempotency_keys.filter(created_at.le(expiry_cutoff()))).execute(conn)
+}
+
+/// Spawns a background task that purges expired keys every [`PURGE_INTERVAL`]
+/// until `stop` resolves. A purge already running when `stop` resolves completes.
+pub fn spawn_purge_task(
+    pool: Arc<db::DbPool>,
+    stop: impl Future<Output = ()> + Send + 'static,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        tokio::pin!(stop);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = &mut stop => break,
+            }
+            match pool.run(purge_expired).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::info!(purged, "purged expired idempotency keys"),
+                Err(e) => tracing::error!(error = %e, "failed to purge idempotency keys"),
+            }
+        }
+    })
+}
+
+fn json_response(status: StatusCode, body: String) -> warp::reply::Response {
+    let mut response = Response::new(body.into());
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+fn expiry_cutoff() -> i64 {
+    now() - KEY_TTL.as_secs() as i64
+}
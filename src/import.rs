@@ -0,0 +1,149 @@
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use utoipa::IntoParams;
+use warp::hyper::body::Bytes;
+use warp::{Filter, Rejection};
+
+use crate::errors::Error;
+use crate::formats::Format;
+use crate::models::{ImportReport, ImportStatus, ImportedRow, NewBook};
+use crate::repository::BookRepository;
+use crate::{compression, csv};
+
+/// Books inserted per transaction.
+pub const BATCH_SIZE: usize = 500;
+
+/// The query parameters of `POST /books/import`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    /// Validates every row and reports the outcome without creating anything.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Reads an import body: CSV with a header row, or JSON Lines, after undoing
+/// any gzip `Content-Encoding` of up to `max_bytes`. Other types are
+/// `415 Unsupported Media Type`.
+pub fn body(max_bytes: u64) -> impl Filter<Extract = (Format, Vec<u8>), Error = Rejection> + Clone {
+    warp::header::optional::<String>("content-type")
+        .and(warp::header::optional::<String>("content-encoding"))
+        .and(warp::body::bytes())
+        .and_then(
+            move |content_type: Option<String>, content_encoding: Option<String>, body: Bytes| async move {
+                let format = match Format::from_content_type(content_type.as_deref()) {
+                    Ok(format @ (Format::Csv | Format::NdJson)) => format,
+                    _ => return Err(warp::reject::custom(Error::UnsupportedMediaType)),
+                };
+                compression::decode_request(content_encoding.as_deref(), &body, max_bytes)
+                    .map(|body| (format, body.into_owned()))
+                    .map_err(warp::reject::custom)
+            },
+        )
+        .untuple_one()
+}
+
+/// Creates a book for every record of `body` that reads as a `T` and passes
+/// `validate`, reporting on each record in turn. A dry run stops after
+/// validation.
+///
+/// Valid books are created [`BATCH_SIZE`] at a time, each batch in one
+/// transaction; a batch that fails is reported row by row and the import
+/// carries on with the next. Only a body that cannot be read at all, such as
+/// a CSV header with a duplicate column, fails the whole request.
+pub async fn books<R, T, V>(
+    repo: Arc<R>,
+    format: Format,
+    body: &[u8],
+    list_fields: &[&str],
+    dry_run: bool,
+    validate: V,
+) -> Result<ImportReport, Error>
+where
+    R: BookRepository,
+    T: DeserializeOwned + Into<NewBook>,
+    V: Fn(&T) -> Result<(), &'static str>,
+{
+    let mut rows = Vec::new();
+    let mut valid = Vec::new();
+    for (i, record) in records(format, body, list_fields)?.into_iter().enumerate() {
+        let book = record
+            .and_then(|value| serde_json::from_value::<T>(value).map_err(|e| e.to_string()))
+            .and_then(|book| validate(&book).map(|()| book).map_err(String::from));
+        let (status, reason) = match book {
+            Ok(book) => {
+                valid.push((rows.len(), book.into()));
+                (ImportStatus::Valid, None)
+            }
+            Err(reason) => (ImportStatus::Failed, Some(reason)),
+        };
+        rows.push(ImportedRow {
+            row: i + 1,
+            status,
+            id: None,
+            reason,
+        });
+    }
+
+    let mut report = ImportReport {
+        dry_run,
+        valid: valid.len(),
+        created: 0,
+        failed: rows.len() - valid.len(),
+        rows,
+    };
+    if dry_run {
+        return Ok(report);
+    }
+
+    let mut valid = valid.into_iter().peekable();
+    while valid.peek().is_some() {
+        let (indices, books): (Vec<usize>, Vec<NewBook>) = valid.by_ref().take(BATCH_SIZE).unzip();
+        match repo.create_many(books).await {
+            Ok(created) => {
+                for (i, book) in indices.into_iter().zip(created) {
+                    let row = &mut report.rows[i];
+                    row.status = ImportStatus::Created;
+                    row.id = Some(book.id);
+                    report.created += 1;
+                }
+            }
+            Err(e) => {
+                tracing::error!(error = %e, rows = indices.len(), "importing a batch of books failed");
+                let reason = match e {
+                    Error::Unavailable(_) | Error::PoolError(_) => {
+                        "database unavailable, retry later"
+                    }
+                    _ => "could not be saved",
+                };
+                for i in indices {
+                    let row = &mut report.rows[i];
+                    row.status = ImportStatus::Failed;
+                    row.reason = Some(reason.to_string());
+                    report.failed += 1;
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Splits `body` into records, each an object or the reason it is unreadable.
+fn records(
+    format: Format,
+    body: &[u8],
+    list_fields: &[&str],
+) -> Result<Vec<Result<Value, String>>, Error> {
+    if format == Format::Csv {
+        let body = std::str::from_utf8(body).map_err(|_| Error::InvalidData)?;
+        return csv::records(body, list_fields).map_err(|_| Error::InvalidData);
+    }
+    Ok(body
+        .split(|&byte| byte == b'\n')
+        .filter(|line| !line.trim_ascii().is_empty())
+        .map(|line| serde_json::from_slice(line).map_err(|e| e.to_string()))
+        .collect())
+}
@@ -1,145 +1,773 @@
 #[cfg(test)]
 mod tests;
 
+mod access_log;
+mod circuit_breaker;
+mod compression;
+mod config;
+mod cors;
+mod csv;
 mod db;
+mod docs_assets;
 mod errors;
+mod export;
+mod fields;
+mod formats;
 mod handlers;
+mod health;
+mod idempotency;
+mod import;
+mod metrics;
 mod models;
+mod msgpack;
+mod repository;
+mod request_id;
 mod schema;
+mod shutdown;
+mod streaming;
+mod telemetry;
+mod versioning;
+mod xml;
+mod yaml;
 
-use models::{Book, NewBook};
+use clap::Parser;
+use config::{BookStore, Config};
+use formats::Documented;
+use models::{
+    Book, ComponentHealth, DbDiagnostics, ErrorBody, HealthReport, HealthStatus, ImportReport,
+    ImportStatus, ImportedRow, NewBook, PoolStatus, Probe,
+};
+use repository::{BookRepository, InMemoryBookRepository};
+use shutdown::Shutdown;
 use std::sync::Arc;
 
 use warp::{Filter, Reply};
 
 use utoipa::OpenApi;
 
-use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
 
-pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+#[cfg(feature = "sqlite")]
+pub const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/sqlite");
+#[cfg(feature = "postgres")]
+pub const POSTGRES_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/postgres");
 
+/// The OpenAPI document of `/v1`, also served by the unversioned paths. Bump
+/// `info.version` with every change to the contract: the minor version for
+/// additions, the major one for breaking changes.
 #[derive(OpenApi)]
 #[openapi(
     paths(
         crate::handlers::list_books,
+        crate::handlers::head_books,
+        crate::handlers::books_options,
         crate::handlers::create_book,
+        crate::handlers::import_books,
+        crate::handlers::import_options,
+        crate::handlers::export_books,
+        crate::handlers::head_export,
+        crate::handlers::export_options,
         crate::handlers::get_book,
+        crate::handlers::head_book,
+        crate::handlers::book_options,
         crate::handlers::update_book,
-        crate::handlers::delete_book
+        crate::handlers::delete_book,
+        crate::handlers::db_diagnostics,
+        crate::handlers::healthz,
+        crate::handlers::readyz,
+        crate::handlers::health,
+        crate::handlers::metrics
     ),
     components(
-        schemas(Book, NewBook)
+        schemas(Book, NewBook, ImportReport, ImportedRow, ImportStatus, ErrorBody, DbDiagnostics, PoolStatus, Probe, HealthReport, ComponentHealth, HealthStatus)
+    ),
+    servers(
+        (url = "/", description = "The server hosting this document")
     ),
     tags(
-        (name = "Books", description = "Book management operations")
+        (name = "Books", description = "Book management operations"),
+        (name = "Diagnostics", description = "Runtime configuration and state"),
+        (name = "Health", description = "Liveness, readiness and dependency health")
     ),
+    modifiers(&Documented),
     info(
         title = "Book Management API",
-        version = "1.0.0",
-        description = "A simple API for managing books"
+        version = "1.7.0",
+        description = "A simple API for managing books. It needs no authentication. \
+            `/v1` is deprecated in favor of `/v2`; its responses carry `Deprecation` and `Sunset` headers."
     )
 )]
 struct ApiDocs;
 
+/// The OpenAPI document of `/v2`, versioned like [`ApiDocs`].
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        crate::handlers::v2::list_books,
+        crate::handlers::v2::head_books,
+        crate::handlers::v2::books_options,
+        crate::handlers::v2::create_book,
+        crate::handlers::v2::import_books,
+        crate::handlers::v2::import_options,
+        crate::handlers::v2::export_books,
+        crate::handlers::v2::head_export,
+        crate::handlers::v2::export_options,
+        crate::handlers::v2::get_book,
+        crate::handlers::v2::head_book,
+        crate::handlers::v2::book_options,
+        crate::handlers::v2::update_book,
+        crate::handlers::v2::delete_book
+    ),
+    components(
+        schemas(models::v2::Book, models::v2::NewBook, ImportReport, ImportedRow, ImportStatus, ErrorBody)
+    ),
+    servers(
+        (url = "/", description = "The server hosting this document")
+    ),
+    tags(
+        (name = "Books", description = "Book management operations")
+    ),
+    modifiers(&Documented),
+    info(
+        title = "Book Management API",
+        version = "2.4.0",
+        description = "A simple API for managing books, listing each book's authors separately. It needs no authentication."
+    )
+)]
+struct ApiDocsV2;
+
+/// Paths served by `serve`, used to label request metrics.
+const ROUTE_TEMPLATES: &[&str] = &[
+    "/books",
+    "/books/import",
+    "/books/export",
+    "/books/{id}",
+    "/v1/books",
+    "/v1/books/import",
+    "/v1/books/export",
+    "/v1/books/{id}",
+    "/v2/books",
+    "/v2/books/import",
+    "/v2/books/export",
+    "/v2/books/{id}",
+    "/diagnostics/db",
+    "/healthz",
+    "/readyz",
+    "/health",
+    "/metrics",
+    "/openapi.json",
+    "/openapi.yaml",
+    "/v2/openapi.json",
+    "/v2/openapi.yaml",
+    "/docs",
+    "/docs/assets/{name}",
+    "/redoc",
+];
+
 #[tokio::main]
 async fn main() {
-    let pool = db::establish_connection();
+    dotenv::dotenv().ok();
+
+    let config = match Config::load(&config::Cli::parse(), |name| std::env::var(name).ok()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let telemetry = match telemetry::init(&config.log, &config.telemetry) {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("failed to set up trace export: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let access_log =
+        config.access_log.path.as_ref().map(|path| {
+            match access_log::AccessLog::open(path, &config.access_log) {
+                Ok(log) => Arc::new(log),
+                Err(e) => {
+                    eprintln!("failed to open access log {}: {}", path.display(), e);
+                    std::process::exit(2);
+                }
+            }
+        });
 
-    // Run migrations
-    pool.get()
-        .expect("Failed to get DB connection")
-        .run_pending_migrations(MIGRATIONS)
-        .expect("Failed to run migrations");
+    let pool = db::establish_connection(&config.database);
 
     let pool = Arc::new(pool);
 
-    let api = filters::books(pool);
+    let shutdown = Arc::new(Shutdown::new());
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown::signal().await;
+            shutdown.trigger();
+        }
+    });
 
-    let api_docs = warp::path("openapi.json")
-        .and(warp::get())
-        .map(|| warp::reply::json(&ApiDocs::openapi()));
+    // The memory store is handy for demos; the database still backs diagnostics.
+    // Only the database keeps idempotency keys that need purging.
+    let (drained, purge_task) = match config.features.book_store {
+        BookStore::Memory => {
+            let repo = Arc::new(InMemoryBookRepository::new());
+            let drained = serve(
+                &config,
+                repo,
+                pool.clone(),
+                shutdown.clone(),
+                access_log.clone(),
+            )
+            .await;
+            (drained, None)
+        }
+        BookStore::Database => {
+            let purge_task = idempotency::spawn_purge_task(pool.clone(), shutdown.triggered());
+            let drained = serve(
+                &config,
+                pool.clone(),
+                pool.clone(),
+                shutdown.clone(),
+                access_log.clone(),
+            )
+            .await;
+            (drained, Some(purge_task))
+        }
+    };
+    if !drained {
+        tracing::warn!(
+            drain_timeout = ?config.server.drain_timeout,
+            "in-flight requests still running after the drain timeout, exiting anyway"
+        );
+    }
+
+    if let Some(purge_task) = purge_task {
+        let _ = purge_task.await;
+    }
+    if let Some(log) = access_log {
+        let _ = tokio::task::spawn_blocking(move || log.flush()).await;
+    }
+
+    if let Err(e) = pool.run(db::checkpoint_wal).await {
+        tracing::error!(error = %e, "failed to checkpoint the WAL");
+    }
+
+    tracing::info!("server stopped");
+    telemetry.shutdown();
+}
+
+/// Serves the API until `shutdown` is triggered; returns whether in-flight requests drained.
+async fn serve<R: BookRepository>(
+    config: &Config,
+    repo: Arc<R>,
+    pool: Arc<db::DbPool>,
+    shutdown: Arc<Shutdown>,
+    access_log: Option<Arc<access_log::AccessLog>>,
+) -> bool {
+    let features = &config.features;
+    // The unversioned book paths are deprecated aliases of `/v1`.
+    let limits = &config.limits;
+    let api = filters::books(repo.clone(), limits)
+        .or(warp::path("v1").and(filters::books(repo.clone(), limits)))
+        .or(warp::path("v2").and(filters::v2::books(repo.clone(), limits)))
+        .or(filters::health(pool.clone(), shutdown.clone()))
+        .or(filters::enabled(features.diagnostics).and(filters::diagnostics(pool.clone())))
+        .or(filters::enabled(features.metrics).and(filters::metrics(repo, pool)));
+
+    let api_docs = serve_openapi(ApiDocs::openapi())
+        .or(warp::path("v2").and(serve_openapi(ApiDocsV2::openapi())));
+
+    let asset_urls = docs_assets::Urls::new(features.docs_assets);
 
-    let swagger_ui = serve_swagger_ui();
+    let swagger_ui = serve_swagger_ui(&asset_urls);
 
-    let redoc_ui = serve_redoc_ui();
+    let redoc_ui = serve_redoc_ui(&asset_urls);
 
-    let routes = api
-        .or(api_docs)
-        .or(swagger_ui)
-        .or(redoc_ui)
-        .with(warp::cors().allow_any_origin())
-        .recover(errors::handle_rejection);
+    let docs = filters::enabled(features.docs).and(
+        api_docs
+            .or(swagger_ui)
+            .or(redoc_ui)
+            .or(docs_assets::serve(docs_assets::BUNDLED)),
+    );
 
-    println!("Server started at http://localhost:8001");
-    println!("API documentation available at http://localhost:8001/docs/");
-    warp::serve(routes).run(([0, 0, 0, 0], 8001)).await;
+    let cors =
+        Arc::new(cors::Cors::new(&config.cors).expect("CORS settings are validated on load"));
+    let compression = config
+        .compression
+        .enabled
+        .then(|| Arc::new(compression::Compression::new(&config.compression)));
+    // Boxing erases the deeply nested filter type, which otherwise makes
+    // compiling the wrappers below very memory hungry.
+    let app = cors::wrap(
+        cors,
+        versioning::wrap(
+            filters::body_limit(config.limits.max_body_bytes, config.limits.max_import_bytes)
+                .and(api.or(docs))
+                .recover(errors::handle_rejection),
+        ),
+    )
+    .boxed();
+    // Compression goes outside `request_id`, which rewrites error bodies.
+    let routes = access_log::wrap(
+        access_log,
+        telemetry::wrap(
+            ROUTE_TEMPLATES,
+            compression::wrap(
+                compression,
+                request_id::wrap(ROUTE_TEMPLATES, metrics::instrument(ROUTE_TEMPLATES, app)),
+            ),
+        ),
+    );
+
+    let address = (config.server.bind_address, config.server.port);
+    let docs_enabled = features.docs;
+    shutdown::serve(
+        routes,
+        address,
+        &shutdown,
+        config.server.pre_stop_delay,
+        config.server.drain_timeout,
+        |address| {
+            tracing::info!("server started at http://{}", address);
+            if docs_enabled {
+                tracing::info!("API documentation available at http://{}/docs/", address);
+            }
+        },
+    )
+    .await
 }
 
 mod filters {
     use super::*;
+    use crate::export::ExportQuery;
+    use crate::fields::FieldsQuery;
+    use crate::formats::{self, Format};
     use crate::handlers;
+    use crate::import::{self, ImportQuery};
+    use crate::repository::BookRepository;
     use std::sync::Arc;
+    use warp::http::Method;
+    use warp::path::FullPath;
     use warp::{Filter, Rejection, Reply};
 
-    pub fn books(
-        db: Arc<db::DbPool>,
-    ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-        get_books(db.clone())
-            .or(get_book(db.clone()))
-            .or(create_book(db.clone()))
-            .or(update_book(db.clone()))
-            .or(delete_book(db))
+    /// The book routes; `limits` caps what request bodies may inflate to.
+    pub fn books<R: BookRepository>(
+        repo: Arc<R>,
+        limits: &config::LimitsConfig,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        get_books(repo.clone())
+            .or(get_book(repo.clone()))
+            .or(create_book(repo.clone(), limits.max_body_bytes))
+            .or(import_books(repo.clone(), limits.max_import_bytes))
+            .or(export_books(repo.clone()))
+            .or(update_book(repo.clone(), limits.max_body_bytes))
+            .or(delete_book(repo))
+            .or(book_options())
     }
 
-    pub fn get_books(
-        db: Arc<db::DbPool>,
+    pub fn get_books<R: BookRepository>(
+        repo: Arc<R>,
     ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-        warp::path("books")
+        let books = warp::path("books")
             // Careful! Omitting the following line would make this filter match requests to /books/:i32 as well.
-            .and(warp::path::end())
+            .and(warp::path::end());
+
+        let get = books
             .and(warp::get())
-            .and(with_db(db))
-            .and_then(handlers::list_books)
+            .and(read(repo.clone()))
+            .and_then(handlers::list_books);
+        let head = books
+            .and(warp::head())
+            .and(read(repo))
+            .and_then(handlers::head_books);
+        get.or(head)
     }
 
-    pub fn create_book(
-        db: Arc<db::DbPool>,
+    pub fn create_book<R: BookRepository>(
+        repo: Arc<R>,
+        max_bytes: u64,
     ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
         warp::path("books")
+            .and(warp::path::end())
             .and(warp::post())
-            .and(warp::body::json())
-            .and(with_db(db))
+            .and(idempotency::key())
+            .and(formats::body(&[], max_bytes))
+            .and(warp::path::full())
+            .and(with_repo(repo))
             .and_then(handlers::create_book)
     }
 
-    pub fn get_book(
-        db: Arc<db::DbPool>,
+    pub fn import_books<R: BookRepository>(
+        repo: Arc<R>,
+        max_bytes: u64,
     ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-        warp::path!("books" / i32)
+        let import = warp::path!("books" / "import");
+
+        let post = import
+            .and(warp::post())
+            .and(warp::query::<ImportQuery>())
+            .and(import::body(max_bytes))
+            .and(with_repo(repo))
+            .and_then(handlers::import_books);
+        let options = import
+            .and(allowed(handlers::IMPORT_ALLOW))
+            .and(warp::options())
+            .and_then(handlers::import_options);
+        post.or(options)
+    }
+
+    pub fn export_books<R: BookRepository>(
+        repo: Arc<R>,
+    ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+        let export = warp::path!("books" / "export");
+
+        let get = export
             .and(warp::get())
-            .and(with_db(db))
-            .and_then(handlers::get_book)
+            .and(warp::query::<ExportQuery>())
+            .and(warp::query::<FieldsQuery>())
+            .and(with_repo(repo))
+            .and_then(handlers::export_books);
+        let head = export
+            .and(warp::head())
+            .and(warp::query::<ExportQuery>())
+            .and(warp::query::<FieldsQuery>())
+            .and_then(handlers::head_export);
+        let options = export
+            .and(allowed(handlers::EXPORT_ALLOW))
+            .and(warp::options())
+            .and_then(handlers::export_options);
+        get.or(head).or(options)
     }
 
-    pub fn update_book(
-        db: Arc<db::DbPool>,
+    pub fn get_book<R: BookRepository>(
+        repo: Arc<R>,
+    ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+        let book = warp::path!("books" / i32);
+
+        let get = book
+            .and(warp::get())
+            .and(read(repo.clone()))
+            .and_then(handlers::get_book);
+        let head = book
+            .and(warp::head())
+            .and(read(repo))
+            .and_then(handlers::head_book);
+        get.or(head)
+    }
+
+    /// Answers `OPTIONS` on the book routes, and `405 Method Not Allowed` to
+    /// methods they do not support, both with an `Allow` header.
+    pub fn book_options() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+        let collection = warp::path("books")
+            .and(warp::path::end())
+            .and(allowed(handlers::BOOKS_ALLOW))
+            .and(warp::options())
+            .and_then(handlers::books_options);
+        let item = warp::path!("books" / i32)
+            .and(allowed(handlers::BOOK_ALLOW))
+            .and(warp::options())
+            .and_then(handlers::book_options);
+        collection.or(item)
+    }
+
+    /// Rejects methods missing from `allow` with `Error::MethodNotAllowed`,
+    /// which outranks the plain 405 of warp's method filters.
+    fn allowed(allow: &'static str) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+        warp::method()
+            .and_then(move |method: warp::http::Method| async move {
+                if allow.split(", ").any(|allowed| allowed == method.as_str()) {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(errors::Error::MethodNotAllowed(allow)))
+                }
+            })
+            .untuple_one()
+    }
+
+    pub fn update_book<R: BookRepository>(
+        repo: Arc<R>,
+        max_bytes: u64,
     ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
         warp::path!("books" / i32)
             .and(warp::put())
-            .and(warp::body::json())
-            .and(with_db(db))
+            .and(formats::body(&[], max_bytes))
+            .and(with_repo(repo))
             .and_then(handlers::update_book)
     }
 
-    pub fn delete_book(
-        db: Arc<db::DbPool>,
+    pub fn delete_book<R: BookRepository>(
+        repo: Arc<R>,
     ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
         warp::path!("books" / i32)
             .and(warp::delete())
-            .and(with_db(db))
+            .and(with_repo(repo))
             .and_then(handlers::delete_book)
     }
 
+    /// Book routes of `/v2`, relative to the `/v2` prefix.
+    pub mod v2 {
+        use super::{allowed, read, with_repo};
+        use crate::config;
+        use crate::export::ExportQuery;
+        use crate::fields::FieldsQuery;
+        use crate::formats;
+        use crate::handlers::{self, v2};
+        use crate::idempotency;
+        use crate::import::{self, ImportQuery};
+        use crate::models::v2::NewBook;
+        use crate::repository::BookRepository;
+        use std::sync::Arc;
+        use warp::{Filter, Rejection, Reply};
+
+        /// The book routes; `limits` caps what request bodies may inflate to.
+        pub fn books<R: BookRepository>(
+            repo: Arc<R>,
+            limits: &config::LimitsConfig,
+        ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+            get_books(repo.clone())
+                .or(get_book(repo.clone()))
+                .or(create_book(repo.clone(), limits.max_body_bytes))
+                .or(import_books(repo.clone(), limits.max_import_bytes))
+                .or(export_books(repo.clone()))
+                .or(update_book(repo.clone(), limits.max_body_bytes))
+                .or(delete_book(repo))
+                .or(book_options())
+        }
+
+        pub fn get_books<R: BookRepository>(
+            repo: Arc<R>,
+        ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+            let books = warp::path("books").and(warp::path::end());
+
+            let get = books
+                .and(warp::get())
+                .and(read(repo.clone()))
+                .and_then(v2::list_books);
+            let head = books
+                .and(warp::head())
+                .and(read(repo))
+                .and_then(v2::head_books);
+            get.or(head)
+        }
+
+        pub fn create_book<R: BookRepository>(
+            repo: Arc<R>,
+            max_bytes: u64,
+        ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+            warp::path("books")
+                .and(warp::path::end())
+                .and(warp::post())
+                .and(idempotency::key())
+                .and(formats::body(NewBook::LIST_FIELDS, max_bytes))
+                .and(warp::path::full())
+                .and(with_repo(repo))
+                .and_then(v2::create_book)
+        }
+
+        pub fn import_books<R: BookRepository>(
+            repo: Arc<R>,
+            max_bytes: u64,
+        ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+            let import = warp::path!("books" / "import");
+
+            let post = import
+                .and(warp::post())
+                .and(warp::query::<ImportQuery>())
+                .and(import::body(max_bytes))
+                .and(with_repo(repo))
+                .and_then(v2::import_books);
+            let options = import
+                .and(allowed(handlers::IMPORT_ALLOW))
+                .and(warp::options())
+                .and_then(v2::import_options);
+            post.or(options)
+        }
+
+        pub fn export_books<R: BookRepository>(
+            repo: Arc<R>,
+        ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+            let export = warp::path!("books" / "export");
+
+            let get = export
+                .and(warp::get())
+                .and(warp::query::<ExportQuery>())
+                .and(warp::query::<FieldsQuery>())
+                .and(with_repo(repo))
+                .and_then(v2::export_books);
+            let head = export
+                .and(warp::head())
+                .and(warp::query::<ExportQuery>())
+                .and(warp::query::<FieldsQuery>())
+                .and_then(v2::head_export);
+            let options = export
+                .and(allowed(handlers::EXPORT_ALLOW))
+                .and(warp::options())
+                .and_then(v2::export_options);
+            get.or(head).or(options)
+        }
+
+        pub fn get_book<R: BookRepository>(
+            repo: Arc<R>,
+        ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+            let book = warp::path!("books" / i32);
+
+            let get = book
+                .and(warp::get())
+                .and(read(repo.clone()))
+                .and_then(v2::get_book);
+            let head = book
+                .and(warp::head())
+                .and(read(repo))
+                .and_then(v2::head_book);
+            get.or(head)
+        }
+
+        pub fn book_options() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+            let collection = warp::path("books")
+                .and(warp::path::end())
+                .and(allowed(handlers::BOOKS_ALLOW))
+                .and(warp::options())
+                .and_then(v2::books_options);
+            let item = warp::path!("books" / i32)
+                .and(allowed(handlers::BOOK_ALLOW))
+                .and(warp::options())
+                .and_then(v2::book_options);
+            collection.or(item)
+        }
+
+        pub fn update_book<R: BookRepository>(
+            repo: Arc<R>,
+            max_bytes: u64,
+        ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+            warp::path!("books" / i32)
+                .and(warp::put())
+                .and(formats::body(NewBook::LIST_FIELDS, max_bytes))
+                .and(with_repo(repo))
+                .and_then(v2::update_book)
+        }
+
+        pub fn delete_book<R: BookRepository>(
+            repo: Arc<R>,
+        ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+            warp::path!("books" / i32)
+                .and(warp::delete())
+                .and(with_repo(repo))
+                .and_then(v2::delete_book)
+        }
+    }
+
+    pub fn diagnostics(
+        db: Arc<db::DbPool>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::path!("diagnostics" / "db")
+            .and(warp::get())
+            .and(with_db(db))
+            .and_then(handlers::db_diagnostics)
+    }
+
+    /// `/healthz`, `/readyz` and `/health`.
+    pub fn health(
+        db: Arc<db::DbPool>,
+        shutdown: Arc<Shutdown>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        let healthz = warp::path!("healthz")
+            .and(warp::get())
+            .and_then(handlers::healthz);
+        let readyz = warp::path!("readyz")
+            .and(warp::get())
+            .and(with_db(db.clone()))
+            .and(with_shutdown(shutdown.clone()))
+            .and_then(handlers::readyz);
+        let health = warp::path!("health")
+            .and(warp::get())
+            .and(with_db(db))
+            .and(with_shutdown(shutdown))
+            .and_then(handlers::health);
+
+        healthz.or(readyz).or(health)
+    }
+
+    pub fn metrics<R: BookRepository>(
+        repo: Arc<R>,
+        db: Arc<db::DbPool>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::path!("metrics")
+            .and(warp::get())
+            .and(with_repo(repo))
+            .and(with_db(db))
+            .and_then(handlers::metrics)
+    }
+
+    /// Passes requests through only while `enabled`, so disabled routes answer 404.
+    pub fn enabled(enabled: bool) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+        warp::any()
+            .and_then(move || async move {
+                if enabled {
+                    Ok(())
+                } else {
+                    Err(warp::reject::not_found())
+                }
+            })
+            .untuple_one()
+    }
+
+    /// Rejects requests whose declared `Content-Length` exceeds `max_bytes`,
+    /// or `max_import_bytes` for bulk imports.
+    ///
+    /// Bodies must declare their length, so that the server never reads more
+    /// than the limit: a `POST`, `PUT` or `PATCH` without `Content-Length`,
+    /// such as a chunked upload, is `411 Length Required`.
+    pub fn body_limit(
+        max_bytes: u64,
+        max_import_bytes: u64,
+    ) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+        warp::method()
+            .and(warp::path::full())
+            .and(warp::header::optional::<u64>("content-length"))
+            .and_then(
+                move |method: Method, path: FullPath, length: Option<u64>| async move {
+                    let max_bytes = if path.as_str().ends_with("/books/import") {
+                        max_import_bytes
+                    } else {
+                        max_bytes
+                    };
+                    match length {
+                        Some(length) if length > max_bytes => {
+                            Err(warp::reject::custom(errors::Error::PayloadTooLarge))
+                        }
+                        None if [Method::POST, Method::PUT, Method::PATCH].contains(&method) => {
+                            Err(warp::reject::custom(errors::Error::LengthRequired))
+                        }
+                        _ => Ok(()),
+                    }
+                },
+            )
+            .untuple_one()
+    }
+
+    /// The query, response format and repository of a book read.
+    fn read<R: BookRepository>(
+        repo: Arc<R>,
+    ) -> impl Filter<Extract = (FieldsQuery, Format, Arc<R>), Error = Rejection> + Clone {
+        warp::query::<FieldsQuery>()
+            .and(formats::accept())
+            .and(with_repo(repo))
+    }
+
+    fn with_repo<R: BookRepository>(
+        repo: Arc<R>,
+    ) -> impl Filter<Extract = (Arc<R>,), Error = std::convert::Infallible> + Clone {
+        warp::any().map(move || repo.clone())
+    }
+
+    fn with_shutdown(
+        shutdown: Arc<Shutdown>,
+    ) -> impl Filter<Extract = (Arc<Shutdown>,), Error = std::convert::Infallible> + Clone {
+        warp::any().map(move || shutdown.clone())
+    }
+
     fn with_db(
         db: Arc<db::DbPool>,
     ) -> impl Filter<Extract = (Arc<db::DbPool>,), Error = std::convert::Infallible> + Clone {
@@ -147,12 +775,27 @@ mod filters {
     }
 }
 
-fn serve_swagger_ui() -> impl Filter<Extract = impl Reply, Error = warp::Rejection> + Clone {
-    warp::path("docs")
-    .and(warp::get())
-    .and(warp::path::end())
-    .map(|| {
-        warp::reply::html(r#"
+/// Serves `document` as `openapi.json` and `openapi.yaml`.
+fn serve_openapi(
+    document: utoipa::openapi::OpenApi,
+) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+    let document = serde_json::to_value(document).expect("OpenAPI serializes");
+    let yaml = yaml::to_string(&document);
+
+    let json = warp::path!("openapi.json")
+        .and(warp::get())
+        .map(move || warp::reply::json(&document));
+    let yaml = warp::path!("openapi.yaml")
+        .and(warp::get())
+        .map(move || warp::reply::with_header(yaml.clone(), "content-type", "application/yaml"));
+    json.or(yaml)
+}
+
+fn serve_swagger_ui(
+    urls: &docs_assets::Urls,
+) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+    let html = format!(
+        r#"
             <!DOCTYPE html>
             <html lang="en">
             <head>
@@ -160,38 +803,48 @@ fn serve_swagger_ui() -> impl Filter<Extract = impl Reply, Error = warp::Rejecti
                 <meta name="viewport" content="width=device-width, initial-scale=1" />
                 <meta name="description" content="SwaggerUI" />
                 <title>SwaggerUI</title>
-                <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui.css" />
+                <link rel="stylesheet" href="{css}" />
             </head>
             <body>
                 <div id="swagger-ui"></div>
-                <script src="https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui-bundle.js" crossorigin></script>
+                <script src="{js}" crossorigin></script>
                 <script>
-                    window.onload = () => {
-                        window.ui = SwaggerUIBundle({
+                    window.onload = () => {{
+                        window.ui = SwaggerUIBundle({{
                             url: '/openapi.json',
                             dom_id: '#swagger-ui',
-                        });
-                    };
+                        }});
+                    }};
                 </script>
             </body>
             </html>
-            "#.to_string())
-    })
-}
-
-fn serve_redoc_ui() -> impl Filter<Extract = impl Reply, Error = warp::Rejection> + Clone {
-    warp::path("redoc")
+            "#,
+        css = urls.swagger_ui_css,
+        js = urls.swagger_ui_js,
+    );
+    warp::path("docs")
         .and(warp::get())
         .and(warp::path::end())
-        .map(|| {
-            warp::reply::html(r#"
+        .map(move || warp::reply::html(html.clone()))
+}
+
+fn serve_redoc_ui(
+    urls: &docs_assets::Urls,
+) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+    let fonts = urls
+        .fonts_css
+        .as_ref()
+        .map(|href| format!(r#"<link href="{}" rel="stylesheet">"#, href))
+        .unwrap_or_default();
+    let html = format!(
+        r#"
                 <!DOCTYPE html>
                 <html>
                 <head>
                     <title>API Documentation</title>
                     <meta charset="utf-8"/>
                     <meta name="viewport" content="width=device-width, initial-scale=1">
-                    <link href="https://fonts.googleapis.com/css?family=Montserrat:300,400,700|Roboto:300,400,700" rel="stylesheet">
+                    {fonts}
                     <style>
                         body {{
                             margin: 0;
@@ -201,9 +854,14 @@ fn serve_redoc_ui() -> impl Filter<Extract = impl Reply, Error = warp::Rejection
                 </head>
                 <body>
                     <redoc spec-url='/openapi.json'></redoc>
-                    <script src="https://cdn.redoc.ly/redoc/latest/bundles/redoc.standalone.js"> </script>
+                    <script src="{js}"> </script>
                 </body>
                 </html>
-                "#.to_string())
-        })
+                "#,
+        js = urls.redoc_js,
+    );
+    warp::path("redoc")
+        .and(warp::get())
+        .and(warp::path::end())
+        .map(move || warp::reply::html(html.clone()))
 }
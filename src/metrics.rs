@@ -0,0 +1,214 @@
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use warp::http::{Method, StatusCode};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::models::PoolStatus;
+
+/// Route label for requests that match no known template, so that
+/// arbitrary paths cannot blow up label cardinality.
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Process-wide metrics, exported by [`render`].
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_query_duration: HistogramVec,
+    pool_wait_duration: Histogram,
+    pool_max_size: IntGauge,
+    pool_connections: IntGauge,
+    pool_idle_connections: IntGauge,
+    pool_queued_jobs: IntGauge,
+    books: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time to produce an HTTP response",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let db_query_duration = HistogramVec::new(
+            HistogramOpts::new("db_query_duration_seconds", "Time spent in db:: functions")
+                .buckets(LATENCY_BUCKETS.to_vec()),
+            &["function"],
+        )
+        .unwrap();
+        let pool_wait_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "db_pool_wait_seconds",
+                "Time spent waiting to check out a pooled connection",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+        )
+        .unwrap();
+        let pool_max_size =
+            IntGauge::new("db_pool_max_size", "Maximum connections in the pool").unwrap();
+        let pool_connections =
+            IntGauge::new("db_pool_connections", "Connections currently open").unwrap();
+        let pool_idle_connections =
+            IntGauge::new("db_pool_idle_connections", "Open connections not in use").unwrap();
+        let pool_queued_jobs = IntGauge::new(
+            "db_queued_jobs",
+            "DB jobs running or waiting for a connection",
+        )
+        .unwrap();
+        let books = IntGauge::new("books", "Books in the store").unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_query_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(pool_wait_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(pool_max_size.clone())).unwrap();
+        registry
+            .register(Box::new(pool_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(pool_idle_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(pool_queued_jobs.clone()))
+            .unwrap();
+        registry.register(Box::new(books.clone())).unwrap();
+
+        Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_query_duration,
+            pool_wait_duration,
+            pool_max_size,
+            pool_connections,
+            pool_idle_connections,
+            pool_queued_jobs,
+            books,
+        }
+    }
+
+    fn observe_request(&self, method: &Method, route: &str, status: StatusCode, elapsed: Duration) {
+        let labels = [method.as_str(), route, status.as_str()];
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_request_duration
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_pool_wait(&self, elapsed: Duration) {
+        self.pool_wait_duration.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn set_pool_status(&self, status: &PoolStatus) {
+        self.pool_max_size.set(status.max_size.into());
+        self.pool_connections.set(status.connections.into());
+        self.pool_idle_connections
+            .set(status.idle_connections.into());
+        self.pool_queued_jobs.set(status.queued_jobs as i64);
+    }
+
+    pub fn set_book_count(&self, count: i64) {
+        self.books.set(count);
+    }
+}
+
+/// Runs `query` in a `db.query` span, recording its duration under the `function` label.
+pub fn time_query<T>(function: &str, query: impl FnOnce() -> T) -> T {
+    let _span = tracing::info_span!(
+        "db.query",
+        otel.name = %format!("db {}", function),
+        otel.kind = "client",
+        db.operation.name = function,
+    )
+    .entered();
+    let started = Instant::now();
+    let result = query();
+    METRICS
+        .db_query_duration
+        .with_label_values(&[function])
+        .observe(started.elapsed().as_secs_f64());
+    result
+}
+
+/// Encodes every metric in the Prometheus text format.
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&METRICS.registry.gather(), &mut buffer)
+        .expect("metrics encode as text");
+    String::from_utf8(buffer).expect("metrics text is UTF-8")
+}
+
+/// Counts and times every response of `filter`, labelled by the first of
+/// `templates` matching the request path, e.g. `/books/{id}`.
+pub fn instrument<F, T>(
+    templates: &'static [&'static str],
+    filter: F,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (T,), Error = Rejection> + Clone + Send + Sync + 'static,
+    T: Reply,
+{
+    warp::any()
+        .map(Instant::now)
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(filter)
+        .map(
+            move |started: Instant, method: Method, path: FullPath, reply: T| {
+                let response = reply.into_response();
+                METRICS.observe_request(
+                    &method,
+                    route_template(templates, path.as_str()),
+                    response.status(),
+                    started.elapsed(),
+                );
+                response
+            },
+        )
+}
+
+/// Returns the template matching `path`, where a `{name}` segment matches any one segment.
+pub fn route_template(templates: &[&'static str], path: &str) -> &'static str {
+    let segments = path.trim_end_matches('/').split('/').collect::<Vec<_>>();
+    templates
+        .iter()
+        .find(|template| {
+            let template = template.split('/').collect::<Vec<_>>();
+            template.len() == segments.len()
+                && template.iter().zip(&segments).all(|(expected, actual)| {
+                    (expected.starts_with('{') && !actual.is_empty()) || expected == actual
+                })
+        })
+        .copied()
+        .unwrap_or(UNMATCHED_ROUTE)
+}
@@ -3,10 +3,10 @@ use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
-#[derive(Queryable, Serialize, Deserialize, ToSchema)]
+#[derive(Queryable, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Book {
     #[schema(example = 1)]
-    pub id: Option<i32>,
+    pub id: i32,
     #[schema(example = "The Rust Programming Language")]
     pub title: String,
     #[schema(example = "Steve Klabnik and Carol Nichols")]
@@ -17,7 +17,15 @@ pub struct Book {
     pub cover_image: String,
 }
 
-#[derive(Insertable, Serialize, Deserialize, ToSchema)]
+impl Book {
+    /// Names `?fields=` may select.
+    pub const FIELDS: &'static [&'static str] =
+        &["id", "title", "author", "date_published", "cover_image"];
+    /// Related resources `?expand=` may embed; books have none yet.
+    pub const RELATIONS: &'static [&'static str] = &[];
+}
+
+#[derive(Insertable, AsChangeset, Serialize, Deserialize, ToSchema)]
 #[diesel(table_name = books)]
 pub struct NewBook {
     #[schema(example = "The Rust Programming Language")]
@@ -29,3 +37,268 @@ pub struct NewBook {
     #[schema(example = "https://example.com/book-cover.jpg")]
     pub cover_image: String,
 }
+
+impl NewBook {
+    /// Checks the rules every created or updated book must meet.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.title.is_empty() {
+            return Err("title must not be empty");
+        }
+        if self.author.is_empty() {
+            return Err("author must not be empty");
+        }
+        Ok(())
+    }
+}
+
+/// What became of one row of a bulk import.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Created,
+    /// Passed validation in a dry run, so nothing was written.
+    Valid,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ImportedRow {
+    /// The row's position among the records, counting from 1; a CSV header
+    /// and blank lines are not counted.
+    #[schema(example = 1)]
+    pub row: usize,
+    pub status: ImportStatus,
+    /// The created book's id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = 42)]
+    pub id: Option<i32>,
+    /// Why the row was not created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "title must not be empty")]
+    pub reason: Option<String>,
+}
+
+/// Outcome of `POST /books/import`, row by row.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    /// Rows that passed validation.
+    #[schema(example = 2)]
+    pub valid: usize,
+    #[schema(example = 2)]
+    pub created: usize,
+    #[schema(example = 1)]
+    pub failed: usize,
+    pub rows: Vec<ImportedRow>,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(QueryableByName, Serialize, ToSchema)]
+pub struct PragmaValues {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    #[schema(example = "wal")]
+    pub journal_mode: String,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    #[schema(example = 5000)]
+    pub busy_timeout_ms: i64,
+    #[diesel(sql_type = diesel::sql_types::Bool)]
+    #[schema(example = true)]
+    pub foreign_keys: bool,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    #[schema(example = "NORMAL")]
+    pub synchronous: String,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    #[schema(example = -64000)]
+    pub cache_size: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PoolStatus {
+    #[schema(example = 10)]
+    pub max_size: u32,
+    #[schema(example = json!(null))]
+    pub min_idle: Option<u32>,
+    #[schema(example = 3)]
+    pub connections: u32,
+    #[schema(example = 2)]
+    pub idle_connections: u32,
+    #[schema(example = 30000)]
+    pub connection_timeout_ms: u64,
+    #[schema(example = 0)]
+    pub queued_jobs: usize,
+}
+
+#[cfg(feature = "postgres")]
+#[derive(QueryableByName, Serialize, ToSchema)]
+pub struct PgSettings {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    #[schema(example = "16.2")]
+    pub server_version: String,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    #[schema(example = "30s")]
+    pub statement_timeout: String,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    #[schema(example = "5s")]
+    pub lock_timeout: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DbDiagnostics {
+    /// Present on SQLite.
+    #[cfg(feature = "sqlite")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pragmas: Option<PragmaValues>,
+    /// Present on Postgres.
+    #[cfg(feature = "postgres")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settings: Option<PgSettings>,
+    pub pool: PoolStatus,
+}
+
+/// Body of every error response.
+#[derive(Serialize, Deserialize, ToSchema)]
+#[schema(example = json!({"status": 404, "error": "Not Found", "request_id": "0b9c7a52-5a4e-4d8e-9d0b-3f1f6c2d8e11"}))]
+pub struct ErrorBody {
+    /// The HTTP status code, repeated for clients that only see the body.
+    pub status: u16,
+    pub error: String,
+    /// The request's `X-Request-Id`, to quote when reporting a problem.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+/// Outcome of a liveness or readiness probe.
+#[derive(Serialize, ToSchema)]
+pub struct Probe {
+    pub status: HealthStatus,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ComponentHealth {
+    #[schema(example = "database")]
+    pub name: String,
+    pub status: HealthStatus,
+    /// Time the check took; absent for checks that do no I/O.
+    #[schema(example = 1.25)]
+    pub latency_ms: Option<f64>,
+    #[schema(example = json!(null))]
+    pub detail: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HealthReport {
+    /// `up` only when every component is up.
+    pub status: HealthStatus,
+    pub components: Vec<ComponentHealth>,
+}
+
+/// Book representations served under `/v2`, which list authors separately.
+///
+/// Storage keeps a single `author` column, as `/v1` exposes it; `/v2` joins
+/// and splits it on [`v2::AUTHOR_SEPARATOR`].
+pub mod v2 {
+    use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+
+    pub const AUTHOR_SEPARATOR: &str = "; ";
+
+    #[derive(Clone, Serialize, Deserialize, ToSchema)]
+    pub struct Book {
+        #[schema(example = 1)]
+        pub id: i32,
+        #[schema(example = "The Rust Programming Language")]
+        pub title: String,
+        #[schema(example = json!(["Steve Klabnik", "Carol Nichols"]))]
+        pub authors: Vec<String>,
+        #[schema(example = "2018-08-12")]
+        pub date_published: String,
+        #[schema(example = "https://example.com/book-cover.jpg")]
+        pub cover_image: String,
+    }
+
+    impl Book {
+        /// Names `?fields=` may select.
+        pub const FIELDS: &'static [&'static str] =
+            &["id", "title", "authors", "date_published", "cover_image"];
+        /// Related resources `?expand=` may embed; books have none yet.
+        pub const RELATIONS: &'static [&'static str] = &[];
+    }
+
+    #[derive(Serialize, Deserialize, ToSchema)]
+    pub struct NewBook {
+        #[schema(example = "The Rust Programming Language")]
+        pub title: String,
+        /// At least one name; names may not contain `;`, which separates
+        /// them in CSV.
+        #[schema(example = json!(["Steve Klabnik", "Carol Nichols"]))]
+        pub authors: Vec<String>,
+        #[schema(example = "2018-08-12")]
+        pub date_published: String,
+        #[schema(example = "https://example.com/book-cover.jpg")]
+        pub cover_image: String,
+    }
+
+    impl NewBook {
+        /// Fields CSV bodies carry as `;`-separated lists.
+        pub const LIST_FIELDS: &'static [&'static str] = &["authors"];
+
+        /// Checks the rules every created or updated book must meet.
+        pub fn validate(&self) -> Result<(), &'static str> {
+            if self.title.is_empty() {
+                return Err("title must not be empty");
+            }
+            if self.authors.is_empty() {
+                return Err("authors must name at least one author");
+            }
+            if self
+                .authors
+                .iter()
+                .any(|author| author.trim().is_empty() || author.contains(';'))
+            {
+                return Err("author names must not be blank or contain `;`");
+            }
+            Ok(())
+        }
+    }
+
+    impl From<super::Book> for Book {
+        fn from(book: super::Book) -> Book {
+            Book {
+                id: book.id,
+                title: book.title,
+                authors: book
+                    .author
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|author| !author.is_empty())
+                    .map(String::from)
+                    .collect(),
+                date_published: book.date_published,
+                cover_image: book.cover_image,
+            }
+        }
+    }
+
+    impl From<NewBook> for super::NewBook {
+        fn from(book: NewBook) -> super::NewBook {
+            super::NewBook {
+                title: book.title,
+                author: book
+                    .authors
+                    .iter()
+                    .map(|author| author.trim())
+                    .collect::<Vec<_>>()
+                    .join(AUTHOR_SEPARATOR),
+                date_published: book.date_published,
+                cover_image: book.cover_image,
+            }
+        }
+    }
+}
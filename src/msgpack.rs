@@ -0,0 +1,22 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Deepest nesting [`from_slice`] accepts, so hostile input cannot exhaust the stack.
+const MAX_DEPTH: usize = 32;
+
+/// Encodes `value` as MessagePack, using the smallest encoding of each item.
+pub fn to_vec(value: &Value) -> Vec<u8> {
+    rmp_serde::to_vec(value).expect("JSON values encode as MessagePack")
+}
+
+/// Decodes a single MessagePack value. Map keys must be strings; binary and
+/// extension types are rejected, as JSON has nothing to map them to.
+pub fn from_slice(bytes: &[u8]) -> Result<Value, String> {
+    let mut deserializer = rmp_serde::Deserializer::new(bytes);
+    deserializer.set_max_depth(MAX_DEPTH);
+    let value = Value::deserialize(&mut deserializer).map_err(|e| e.to_string())?;
+    if !deserializer.into_inner().is_empty() {
+        return Err("trailing bytes after the value".to_string());
+    }
+    Ok(value)
+}
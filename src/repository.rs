@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::Mutex;
+
+use crate::errors::Error;
+use crate::idempotency::{self, IdempotentRequest, Outcome, StoredResponse};
+use crate::models::{Book, NewBook};
+
+/// Storage for books, independent of the database behind it.
+pub trait BookRepository: Send + Sync + 'static {
+    fn list(&self) -> impl Future<Output = Result<Vec<Book>, Error>> + Send;
+
+    /// Up to `limit` books with ids above `after`, in id order.
+    fn page(&self, after: i32, limit: i64)
+        -> impl Future<Output = Result<Vec<Book>, Error>> + Send;
+
+    fn get(&self, id: i32) -> impl Future<Output = Result<Book, Error>> + Send;
+
+    fn count(&self) -> impl Future<Output = Result<i64, Error>> + Send;
+
+    /// Creates a book, or replays the response recorded for `request`'s key.
+    fn create(
+        &self,
+        new_book: NewBook,
+        request: Option<IdempotentRequest>,
+    ) -> impl Future<Output = Result<Outcome<Book>, Error>> + Send;
+
+    /// Creates all of `new_books` or, on error, none of them.
+    fn create_many(
+        &self,
+        new_books: Vec<NewBook>,
+    ) -> impl Future<Output = Result<Vec<Book>, Error>> + Send;
+
+    fn update(
+        &self,
+        id: i32,
+        updated_book: NewBook,
+    ) -> impl Future<Output = Result<Book, Error>> + Send;
+
+    fn delete(&self, id: i32) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+/// Thread-safe repository that keeps books in memory, for tests and demos.
+#[derive(Default)]
+pub struct InMemoryBookRepository {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    last_id: i32,
+    books: BTreeMap<i32, Book>,
+    idempotency_keys: HashMap<String, (String, StoredResponse, i64)>,
+}
+
+impl InMemoryBookRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BookRepository for InMemoryBookRepository {
+    async fn list(&self) -> Result<Vec<Book>, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state.books.values().cloned().collect())
+    }
+
+    async fn page(&self, after: i32, limit: i64) -> Result<Vec<Book>, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .books
+            .range(after.saturating_add(1)..)
+            .take(limit as usize)
+            .map(|(_, book)| book.clone())
+            .collect())
+    }
+
+    async fn get(&self, id: i32) -> Result<Book, Error> {
+        let state = self.state.lock().unwrap();
+        state.books.get(&id).cloned().ok_or(Error::NotFound)
+    }
+
+    async fn count(&self) -> Result<i64, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state.books.len() as i64)
+    }
+
+    async fn create(
+        &self,
+        new_book: NewBook,
+        request: Option<IdempotentRequest>,
+    ) -> Result<Outcome<Book>, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(request) = &request {
+            // Expired keys are dropped here, as the purge task does for the
+            // database.
+            state
+                .idempotency_keys
+                .retain(|_, (_, _, created_at)| !idempotency::is_expired(*created_at));
+            match state.idempotency_keys.get(&request.key) {
+                Some((fingerprint, _, _)) if *fingerprint != request.fingerprint => {
+                    return Err(Error::IdempotencyKeyReused);
+                }
+                Some((_, stored, _)) => return Ok(Outcome::Replayed(stored.clone())),
+                None => {}
+            }
+        }
+
+        state.last_id += 1;
+        let id = state.last_id;
+        let book = Book {
+            id,
+            title: new_book.title,
+            author: new_book.author,
+            date_published: new_book.date_published,
+            cover_image: new_book.cover_image,
+        };
+        state.books.insert(id, book.clone());
+
+        if let Some(request) = request {
+            let stored = StoredResponse {
+                status: request.status,
+                body: serde_json::to_string(&book).unwrap(),
+            };
+            state.idempotency_keys.insert(
+                request.key,
+                (request.fingerprint, stored, idempotency::now()),
+            );
+        }
+
+        Ok(Outcome::Fresh(book))
+    }
+
+    async fn create_many(&self, new_books: Vec<NewBook>) -> Result<Vec<Book>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let mut created = Vec::with_capacity(new_books.len());
+        for new_book in new_books {
+            state.last_id += 1;
+            let book = Book {
+                id: state.last_id,
+                title: new_book.title,
+                author: new_book.author,
+                date_published: new_book.date_published,
+                cover_image: new_book.cover_image,
+            };
+            state.books.insert(book.id, book.clone());
+            created.push(book);
+        }
+        Ok(created)
+    }
+
+    async fn update(&self, id: i32, updated_book: NewBook) -> Result<Book, Error> {
+        let mut state = self.state.lock().unwrap();
+        let book = state.books.get_mut(&id).ok_or(Error::NotFound)?;
+
+        book.title = updated_book.title;
+        book.author = updated_book.author;
+        book.date_published = updated_book.date_published;
+        book.cover_image = updated_book.cover_image;
+
+        Ok(book.clone())
+    }
+
+    async fn delete(&self, id: i32) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        state.books.remove(&id).map(|_| ()).ok_or(Error::NotFound)
+    }
+}
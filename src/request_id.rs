@@ -0,0 +1,82 @@
+use std::time::Instant;
+use warp::http::header::{self, HeaderValue};
+use warp::http::Method;
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::errors::ErrorContext;
+use crate::metrics;
+use crate::models::ErrorBody;
+
+pub const HEADER: &str = "x-request-id";
+
+/// Longest client-supplied ID that is propagated rather than replaced.
+const MAX_LEN: usize = 128;
+
+/// Gives every request an ID and logs it on completion.
+///
+/// A valid incoming `X-Request-Id` is kept, otherwise a UUID is generated.
+/// The ID is echoed in the response header and added to error bodies
+/// produced by `handle_rejection`, whose server-side cause is logged with it.
+pub fn wrap<F, T>(
+    templates: &'static [&'static str],
+    filter: F,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (T,), Error = Rejection> + Clone + Send + Sync + 'static,
+    T: Reply,
+{
+    warp::any()
+        .map(Instant::now)
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(
+            warp::header::optional::<String>(HEADER).map(|id: Option<String>| {
+                let id = id
+                    .filter(|id| is_valid(id))
+                    .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+                tracing::Span::current().record("request_id", id.as_str());
+                id
+            }),
+        )
+        .and(filter)
+        .map(
+            move |started: Instant, method: Method, path: FullPath, id: String, reply: T| {
+                let mut response = reply.into_response();
+                let route = metrics::route_template(templates, path.as_str());
+
+                if let Some(context) = response.extensions_mut().remove::<ErrorContext>() {
+                    let body = ErrorBody {
+                        status: response.status().as_u16(),
+                        error: context.message.to_string(),
+                        request_id: Some(id.clone()),
+                    };
+                    *response.body_mut() = serde_json::to_string(&body)
+                        .expect("error bodies serialize")
+                        .into();
+                    response.headers_mut().remove(header::CONTENT_LENGTH);
+                    if let Some(cause) = context.cause {
+                        tracing::error!(request_id = %id, %method, route, %cause, "request failed");
+                    }
+                }
+                if let Ok(value) = HeaderValue::from_str(&id) {
+                    response.headers_mut().insert(HEADER, value);
+                }
+
+                tracing::info!(
+                    request_id = %id,
+                    %method,
+                    route,
+                    status = response.status().as_u16(),
+                    latency_ms = started.elapsed().as_secs_f64() * 1000.0,
+                    "request completed"
+                );
+                response
+            },
+        )
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
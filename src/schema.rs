@@ -2,10 +2,23 @@
 
 diesel::table! {
     books (id) {
-        id -> Nullable<Integer>,
+        // SQLite reports `INTEGER PRIMARY KEY` as nullable, but it aliases the rowid and is never NULL.
+        id -> Integer,
         title -> Text,
         author -> Text,
         date_published -> Text,
         cover_image -> Text,
     }
 }
+
+diesel::table! {
+    idempotency_keys (key) {
+        key -> Text,
+        fingerprint -> Text,
+        status -> Integer,
+        body -> Text,
+        created_at -> BigInt,
+    }
+}
+
+diesel::allow_tables_to_appear_in_same_query!(books, idempotency_keys,);
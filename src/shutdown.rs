@@ -0,0 +1,105 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::watch;
+use warp::{Filter, Rejection, Reply};
+
+/// Default time in-flight requests get to finish once shutdown starts.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default time between a shutdown signal and closing the listener.
+pub const DEFAULT_PRE_STOP_DELAY: Duration = Duration::ZERO;
+
+/// Broadcasts the start of a graceful shutdown to the server and background tasks.
+pub struct Shutdown {
+    sender: watch::Sender<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown {
+            sender: watch::channel(false).0,
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Resolves once [`Shutdown::trigger`] has been called.
+    pub fn triggered(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut receiver = self.sender.subscribe();
+        async move {
+            // The sender lives as long as `self`; if it is gone, so is everyone waiting.
+            let _ = receiver.wait_for(|triggered| *triggered).await;
+        }
+    }
+}
+
+/// Resolves on SIGINT (Ctrl-C) or, on Unix, SIGTERM.
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl-C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Serves `routes` until `shutdown` is triggered and `pre_stop_delay` has
+/// passed, then stops accepting connections and waits up to `drain_timeout`
+/// for in-flight requests.
+///
+/// During the delay `/readyz` already reports the server down while new
+/// connections are still served, giving load balancers time to stop routing
+/// to it.
+///
+/// Returns whether every in-flight request finished in time.
+pub async fn serve<F>(
+    routes: F,
+    address: impl Into<SocketAddr> + 'static,
+    shutdown: &Shutdown,
+    pre_stop_delay: Duration,
+    drain_timeout: Duration,
+    on_bound: impl FnOnce(SocketAddr),
+) -> bool
+where
+    F: Filter<Error = Rejection> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let (address, server) = warp::serve(routes).bind_with_graceful_shutdown(address, async move {
+        let _ = stop_rx.await;
+    });
+    on_bound(address);
+    let server = tokio::spawn(server);
+
+    shutdown.triggered().await;
+    tokio::time::sleep(pre_stop_delay).await;
+    let _ = stop_tx.send(());
+    tokio::time::timeout(drain_timeout, server).await.is_ok()
+}
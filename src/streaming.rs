@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use tracing::Instrument;
+use warp::http::header::{self, HeaderValue};
+use warp::hyper::Body;
+use warp::reply::Response;
+
+use crate::csv;
+use crate::errors::Error;
+use crate::fields::Fieldset;
+use crate::formats::{self, Format};
+use crate::models::Book;
+use crate::repository::BookRepository;
+
+/// Books read per query while streaming a list.
+pub const PAGE_SIZE: i64 = 500;
+
+/// Answers with every book in `format`, converted by `convert` and trimmed to
+/// `fieldset`.
+///
+/// JSON, JSON Lines and CSV are streamed as a chunked body, one page of ids
+/// at a time, so memory stays bounded however many books there are. Each
+/// page is its own query, which keeps slow clients from holding a pooled
+/// connection. Errors before the first page get the usual status; later
+/// ones cut the body short.
+pub async fn books<R, T, F>(
+    repo: Arc<R>,
+    format: Format,
+    fieldset: Fieldset,
+    columns: &'static [&'static str],
+    convert: F,
+) -> Result<Response, Error>
+where
+    R: BookRepository,
+    T: Serialize,
+    F: Fn(Book) -> T + Send + 'static,
+{
+    if !format.streams() {
+        let books = repo
+            .list()
+            .await?
+            .into_iter()
+            .map(convert)
+            .collect::<Vec<_>>();
+        return Ok(formats::reply(format, &books, &fieldset, "book", columns));
+    }
+
+    let first = repo.page(0, PAGE_SIZE).await?;
+    let mut encoder = Encoder::new(format, fieldset, columns, convert);
+    let mut chunk = encoder.open();
+    let mut after = next_after(&first);
+    encoder.write(&mut chunk, first);
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(
+        async move {
+            if sender.send_data(chunk.into()).await.is_err() {
+                return;
+            }
+            while let Some(last) = after {
+                let page = match repo.page(last, PAGE_SIZE).await {
+                    Ok(page) => page,
+                    Err(e) => {
+                        tracing::error!(error = %e, "streaming books failed");
+                        sender.abort();
+                        return;
+                    }
+                };
+                after = next_after(&page);
+                let mut chunk = Vec::new();
+                encoder.write(&mut chunk, page);
+                // Fails once the client has gone away.
+                if !chunk.is_empty() && sender.send_data(chunk.into()).await.is_err() {
+                    return;
+                }
+            }
+            let _ = sender.send_data(encoder.close().into()).await;
+        }
+        .instrument(tracing::Span::current()),
+    );
+
+    let mut response = Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    headers.insert(header::VARY, HeaderValue::from_static("accept"));
+    Ok(response)
+}
+
+/// The id to read the next page after, or `None` after the last page.
+fn next_after(page: &[Book]) -> Option<i32> {
+    if (page.len() as i64) < PAGE_SIZE {
+        None
+    } else {
+        page.last().map(|book| book.id)
+    }
+}
+
+/// Writes books one page at a time in a streamable format.
+struct Encoder<F> {
+    format: Format,
+    fieldset: Fieldset,
+    columns: Vec<&'static str>,
+    convert: F,
+    written: bool,
+}
+
+impl<T: Serialize, F: Fn(Book) -> T> Encoder<F> {
+    fn new(format: Format, fieldset: Fieldset, columns: &[&'static str], convert: F) -> Self {
+        let columns = columns
+            .iter()
+            .copied()
+            .filter(|column| fieldset.includes(column))
+            .collect();
+        Encoder {
+            format,
+            fieldset,
+            columns,
+            convert,
+            written: false,
+        }
+    }
+
+    fn open(&self) -> Vec<u8> {
+        match self.format {
+            Format::Json => b"[".to_vec(),
+            Format::Csv => {
+                let mut header = String::new();
+                csv::write_header(&mut header, &self.columns);
+                header.into_bytes()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn write(&mut self, out: &mut Vec<u8>, books: Vec<Book>) {
+        for book in books {
+            let record = (self.convert)(book);
+            match self.format {
+                Format::Csv => {
+                    let mut row = String::new();
+                    csv::write_record(&mut row, &self.fieldset.apply(&record), &self.columns);
+                    out.extend_from_slice(row.as_bytes());
+                }
+                format => {
+                    if format == Format::Json && self.written {
+                        out.push(b',');
+                    }
+                    if self.fieldset.is_all() {
+                        // Serializing directly keeps the fields in declaration order.
+                        serde_json::to_writer(&mut *out, &record)
+                    } else {
+                        serde_json::to_writer(&mut *out, &self.fieldset.apply(&record))
+                    }
+                    .expect("books serialize");
+                    if format == Format::NdJson {
+                        out.push(b'\n');
+                    }
+                }
+            }
+            self.written = true;
+        }
+    }
+
+    fn close(&self) -> Vec<u8> {
+        match self.format {
+            Format::Json => b"]".to_vec(),
+            _ => Vec::new(),
+        }
+    }
+}
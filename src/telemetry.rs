@@ -0,0 +1,190 @@
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{ExporterBuildError, Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::field::Empty;
+use tracing::{Level, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+use warp::http::header::{HeaderMap, HeaderName, HeaderValue};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::{LogConfig, LogFormat, LogLevel, OtlpProtocol, TelemetryConfig};
+use crate::metrics;
+
+/// Keeps the trace exporter alive; call [`Telemetry::shutdown`] to flush it before exiting.
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("failed to flush traces: {}", e);
+            }
+        }
+    }
+}
+
+/// Installs the global `tracing` subscriber: logs as described by `log`, plus
+/// OTLP trace export when `telemetry` names an endpoint.
+pub fn init(log: &LogConfig, telemetry: &TelemetryConfig) -> Result<Telemetry, ExporterBuildError> {
+    let provider = telemetry
+        .otlp_endpoint
+        .as_deref()
+        .map(|endpoint| tracer_provider(endpoint, telemetry.otlp_protocol, &telemetry.service_name))
+        .transpose()?;
+
+    let fmt = match log.format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().pretty().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .boxed(),
+    };
+    // `request_id::wrap` logs every completed request; warp's own trace events would repeat it.
+    let targets = Targets::new()
+        .with_default(level(log.level))
+        .with_target("warp::filters::trace", LevelFilter::OFF);
+
+    tracing_subscriber::registry()
+        .with(fmt.with_filter(targets))
+        .with(provider.as_ref().map(|provider| {
+            tracing_opentelemetry::layer()
+                .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+                .with_filter(LevelFilter::INFO)
+        }))
+        .init();
+
+    Ok(Telemetry { provider })
+}
+
+/// Builds a provider that batches spans to the OTLP collector at `endpoint`.
+///
+/// For `http/protobuf`, `endpoint` is the collector's base URL and spans are
+/// posted to `/v1/traces` under it.
+pub fn tracer_provider(
+    endpoint: &str,
+    protocol: OtlpProtocol,
+    service_name: &str,
+) -> Result<SdkTracerProvider, ExporterBuildError> {
+    let exporter = match protocol {
+        OtlpProtocol::Grpc => SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?,
+        OtlpProtocol::HttpProtobuf => SpanExporter::builder()
+            .with_http()
+            .with_protocol(Protocol::HttpBinary)
+            .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            .build()?,
+    };
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(service_name.to_string())
+                .build(),
+        )
+        .build())
+}
+
+/// Runs `filter` inside a span per request, continuing the trace named by an
+/// incoming `traceparent` header and returning the span's own `traceparent`.
+pub fn wrap<F, T>(
+    templates: &'static [&'static str],
+    filter: F,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (T,), Error = Rejection> + Clone + Send + Sync + 'static,
+    T: Reply,
+{
+    filter
+        .map(|reply: T| {
+            let mut response = reply.into_response();
+            let span = Span::current();
+            span.record("http.response.status_code", response.status().as_u16());
+            inject(&span, response.headers_mut());
+            response
+        })
+        .with(warp::trace(move |info| {
+            request_span(
+                templates,
+                info.method(),
+                info.path(),
+                info.request_headers(),
+            )
+        }))
+        .map(Reply::into_response)
+}
+
+/// Creates the span for a request, parented to the caller's `traceparent` if any.
+fn request_span(
+    templates: &[&'static str],
+    method: &warp::http::Method,
+    path: &str,
+    headers: &HeaderMap,
+) -> Span {
+    let route = metrics::route_template(templates, path);
+    let span = tracing::info_span!(
+        "request",
+        otel.name = %format!("{} {}", method, route),
+        otel.kind = "server",
+        http.request.method = %method,
+        http.route = route,
+        url.path = path,
+        http.response.status_code = Empty,
+        request_id = Empty,
+    );
+    let parent = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    let _ = span.set_parent(parent);
+    span
+}
+
+/// Writes `span`'s W3C trace context into `headers`, e.g. for outgoing requests.
+pub fn inject(span: &Span, headers: &mut HeaderMap) {
+    TraceContextPropagator::new().inject_context(&span.context(), &mut HeaderInjector(headers));
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+fn level(level: LogLevel) -> Level {
+    match level {
+        LogLevel::Error => Level::ERROR,
+        LogLevel::Warn => Level::WARN,
+        LogLevel::Info => Level::INFO,
+        LogLevel::Debug => Level::DEBUG,
+        LogLevel::Trace => Level::TRACE,
+    }
+}
@@ -1,24 +1,109 @@
-use diesel::r2d2::{self, ConnectionManager};
-use diesel::sqlite::SqliteConnection;
-use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use diesel::r2d2;
 use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use warp::test::request;
 use warp::Filter;
 
-use crate::{db, errors, filters, models};
+use crate::access_log::{AccessLog, RotatingFile};
+use crate::circuit_breaker::CircuitBreaker;
+use crate::docs_assets::{self, Asset};
+use crate::repository::InMemoryBookRepository;
+use crate::shutdown::{self, Shutdown};
+use crate::{
+    compression, config, cors, db, errors, filters, handlers, metrics, models, request_id,
+    telemetry, versioning,
+};
 
-pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+static NEXT_DATABASE: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns a URL for a new, empty database that several connections can share.
+///
+/// Postgres gets a fresh database on the server named by `TEST_DATABASE_URL`
+/// when that is set; see `scripts/test-postgres.sh`. SQLite gets a file in the
+/// temp directory otherwise.
+fn fresh_database_url() -> String {
+    #[cfg(feature = "postgres")]
+    if let Ok(admin_url) = std::env::var("TEST_DATABASE_URL") {
+        return fresh_postgres_database_url(&admin_url);
+    }
+    #[cfg(feature = "sqlite")]
+    return fresh_sqlite_database_url();
+    #[cfg(not(feature = "sqlite"))]
+    panic!("TEST_DATABASE_URL must point at a Postgres server to test the postgres backend");
+}
+
+#[cfg(feature = "sqlite")]
+fn fresh_sqlite_database_url() -> String {
+    let path = std::env::temp_dir().join(format!(
+        "books-test-{}-{}.db",
+        std::process::id(),
+        NEXT_DATABASE.fetch_add(1, Ordering::SeqCst)
+    ));
+    remove_database(&path.display().to_string());
+    path.display().to_string()
+}
+
+#[cfg(feature = "postgres")]
+fn fresh_postgres_database_url(admin_url: &str) -> String {
+    use diesel::{Connection, PgConnection, RunQueryDsl};
+
+    let name = format!(
+        "books_test_{}_{}",
+        std::process::id(),
+        NEXT_DATABASE.fetch_add(1, Ordering::SeqCst)
+    );
+
+    let conn = &mut PgConnection::establish(admin_url).expect("Failed to connect to Postgres");
+    diesel::sql_query(format!("DROP DATABASE IF EXISTS {}", name))
+        .execute(conn)
+        .expect("Failed to drop test database");
+    diesel::sql_query(format!("CREATE DATABASE {}", name))
+        .execute(conn)
+        .expect("Failed to create test database");
+
+    let (server, _) = admin_url
+        .rsplit_once('/')
+        .expect("TEST_DATABASE_URL must name a database");
+    format!("{}/{}", server, name)
+}
+
+/// Whether the tests run against Postgres rather than SQLite.
+fn testing_postgres() -> bool {
+    cfg!(feature = "postgres") && std::env::var_os("TEST_DATABASE_URL").is_some()
+}
+
+/// Removes an SQLite database file and its WAL; Postgres databases are left
+/// to the throwaway server.
+fn remove_database(url: &str) {
+    if url.starts_with("postgres://") {
+        return;
+    }
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", url, suffix));
+    }
+}
+
+/// Returns a URL for a database used by a single connection.
+fn test_database_url() -> String {
+    if testing_postgres() {
+        fresh_database_url()
+    } else {
+        ":memory:".to_string()
+    }
+}
 
 fn setup_test_db() -> Arc<db::DbPool> {
-    let manager = ConnectionManager::<SqliteConnection>::new(":memory:");
-    let pool = r2d2::Pool::builder()
-        .build(manager)
-        .expect("Failed to create pool.");
+    let pool = db::create_connection_pool(
+        &test_database_url(),
+        &db::PoolOptions::default(),
+        &db::ConnectionOptions::default(),
+    )
+    .expect("Failed to create pool.");
 
     let conn = &mut pool.get().expect("Failed to get connection");
-    conn.run_pending_migrations(MIGRATIONS)
-        .expect("Failed to run migrations");
+    db::run_migrations(conn).expect("Failed to run migrations");
 
     Arc::new(pool)
 }
@@ -26,7 +111,7 @@ fn setup_test_db() -> Arc<db::DbPool> {
 #[tokio::test]
 async fn test_list_books() {
     let db_pool = setup_test_db();
-    let api = filters::books(db_pool);
+    let api = filters::books(db_pool, &Default::default());
 
     let response = request().method("GET").path("/books").reply(&api).await;
 
@@ -36,7 +121,7 @@ async fn test_list_books() {
 #[tokio::test]
 async fn test_create_book() {
     let db_pool = setup_test_db();
-    let api = filters::books(db_pool);
+    let api = filters::books(db_pool, &Default::default());
 
     let new_book = models::NewBook {
         title: "Test Book".to_string(),
@@ -52,13 +137,18 @@ async fn test_create_book() {
         .reply(&api)
         .await;
 
-    assert_eq!(response.status(), 200);
+    assert_eq!(response.status(), 201);
+    let book: models::Book = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(
+        response.headers()["location"],
+        format!("/books/{}", book.id).as_str()
+    );
 }
 
 #[tokio::test]
 async fn test_get_book() {
     let db_pool = setup_test_db();
-    let api = filters::books(db_pool.clone());
+    let api = filters::books(db_pool.clone(), &Default::default());
 
     let new_book = models::NewBook {
         title: "Test Book".to_string(),
@@ -66,9 +156,9 @@ async fn test_get_book() {
         date_published: "2024-01-01".to_string(),
         cover_image: "http://example.com/cover.jpg".to_string(),
     };
-    let book = db::create_book(&db_pool, new_book).unwrap();
+    let book = db::create_book(&mut db_pool.get().unwrap(), new_book).unwrap();
 
-    let book_id = book.id.expect("Book should have an ID");
+    let book_id = book.id;
     let response = request()
         .method("GET")
         .path(&format!("/books/{}", book_id))
@@ -81,7 +171,7 @@ async fn test_get_book() {
 #[tokio::test]
 async fn test_update_book() {
     let db_pool = setup_test_db();
-    let api = filters::books(db_pool.clone());
+    let api = filters::books(db_pool.clone(), &Default::default());
 
     let new_book = models::NewBook {
         title: "Test Book".to_string(),
@@ -89,9 +179,9 @@ async fn test_update_book() {
         date_published: "2024-01-01".to_string(),
         cover_image: "http://example.com/cover.jpg".to_string(),
     };
-    let book = db::create_book(&db_pool, new_book).unwrap();
+    let book = db::create_book(&mut db_pool.get().unwrap(), new_book).unwrap();
 
-    let book_id = book.id.expect("Book should have an ID");
+    let book_id = book.id;
     let updated_book = models::NewBook {
         title: "Updated Test Book".to_string(),
         author: "Updated Test Author".to_string(),
@@ -112,7 +202,7 @@ async fn test_update_book() {
 #[tokio::test]
 async fn test_delete_book() {
     let db_pool = setup_test_db();
-    let api = filters::books(db_pool.clone());
+    let api = filters::books(db_pool.clone(), &Default::default());
 
     let new_book = models::NewBook {
         title: "Test Book".to_string(),
@@ -120,9 +210,9 @@ async fn test_delete_book() {
         date_published: "2024-01-01".to_string(),
         cover_image: "http://example.com/cover.jpg".to_string(),
     };
-    let book = db::create_book(&db_pool, new_book).unwrap();
+    let book = db::create_book(&mut db_pool.get().unwrap(), new_book).unwrap();
 
-    let book_id = book.id.expect("Book should have an ID");
+    let book_id = book.id;
     let response = request()
         .method("DELETE")
         .path(&format!("/books/{}", book_id))
@@ -135,7 +225,7 @@ async fn test_delete_book() {
 #[tokio::test]
 async fn test_get_nonexistent_book() {
     let db_pool = setup_test_db();
-    let api = filters::books(db_pool).recover(errors::handle_rejection);
+    let api = filters::books(db_pool, &Default::default()).recover(errors::handle_rejection);
 
     let response = request()
         .method("GET")
@@ -149,7 +239,7 @@ async fn test_get_nonexistent_book() {
 #[tokio::test]
 async fn test_update_nonexistent_book() {
     let db_pool = setup_test_db();
-    let api = filters::books(db_pool).recover(errors::handle_rejection);
+    let api = filters::books(db_pool, &Default::default()).recover(errors::handle_rejection);
 
     let updated_book = models::NewBook {
         title: "Updated Book".to_string(),
@@ -171,7 +261,7 @@ async fn test_update_nonexistent_book() {
 #[tokio::test]
 async fn test_delete_nonexistent_book() {
     let db_pool = setup_test_db();
-    let api = filters::books(db_pool).recover(errors::handle_rejection);
+    let api = filters::books(db_pool, &Default::default()).recover(errors::handle_rejection);
 
     let response = request()
         .method("DELETE")
@@ -185,7 +275,7 @@ async fn test_delete_nonexistent_book() {
 #[tokio::test]
 async fn test_create_book_invalid_data() {
     let db_pool = setup_test_db();
-    let api = filters::books(db_pool).recover(errors::handle_rejection);
+    let api = filters::books(db_pool, &Default::default()).recover(errors::handle_rejection);
 
     let invalid_book = json!({
         "title": "",
@@ -207,7 +297,8 @@ async fn test_create_book_invalid_data() {
 #[tokio::test]
 async fn test_update_book_invalid_data() {
     let db_pool = setup_test_db();
-    let api = filters::books(db_pool.clone()).recover(errors::handle_rejection);
+    let api =
+        filters::books(db_pool.clone(), &Default::default()).recover(errors::handle_rejection);
 
     let new_book = models::NewBook {
         title: "Test Book".to_string(),
@@ -215,9 +306,9 @@ async fn test_update_book_invalid_data() {
         date_published: "2024-01-01".to_string(),
         cover_image: "http://example.com/cover.jpg".to_string(),
     };
-    let book = db::create_book(&db_pool, new_book).unwrap();
+    let book = db::create_book(&mut db_pool.get().unwrap(), new_book).unwrap();
 
-    let book_id = book.id.expect("Book should have an ID");
+    let book_id = book.id;
     let invalid_book = json!({
         "title": "",
         "author": "",
@@ -234,3 +325,2608 @@ async fn test_update_book_invalid_data() {
 
     assert_eq!(response.status(), 400);
 }
+
+#[tokio::test]
+async fn test_create_book_idempotent_replay() {
+    let db_pool = setup_test_db();
+    let api =
+        filters::books(db_pool.clone(), &Default::default()).recover(errors::handle_rejection);
+
+    let new_book = models::NewBook {
+        title: "Test Book".to_string(),
+        author: "Test Author".to_string(),
+        date_published: "2024-01-01".to_string(),
+        cover_image: "http://example.com/cover.jpg".to_string(),
+    };
+
+    let first = request()
+        .method("POST")
+        .path("/books")
+        .header("Idempotency-Key", "create-test-book")
+        .json(&new_book)
+        .reply(&api)
+        .await;
+    let replay = request()
+        .method("POST")
+        .path("/books")
+        .header("Idempotency-Key", "create-test-book")
+        .json(&new_book)
+        .reply(&api)
+        .await;
+
+    assert_eq!(first.status(), 201);
+    assert_eq!(replay.status(), 201);
+    assert_eq!(replay.headers()["idempotent-replayed"], "true");
+    assert_eq!(first.body(), replay.body());
+    assert_eq!(first.headers()["location"], replay.headers()["location"]);
+    assert_eq!(
+        db::get_all_books(&mut db_pool.get().unwrap())
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_head_options_and_method_not_allowed() {
+    let api =
+        filters::books(setup_test_db(), &Default::default()).recover(errors::handle_rejection);
+
+    for (path, allow) in [
+        ("/books", handlers::BOOKS_ALLOW),
+        ("/books/1", handlers::BOOK_ALLOW),
+    ] {
+        let response = request().method("OPTIONS").path(path).reply(&api).await;
+        assert_eq!(response.status(), 204);
+        assert_eq!(response.headers()["allow"], allow);
+        assert!(response.body().is_empty());
+
+        let response = request().method("PATCH").path(path).reply(&api).await;
+        assert_eq!(response.status(), 405);
+        assert_eq!(response.headers()["allow"], allow);
+        let body: models::ErrorBody = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body.error, "Method Not Allowed");
+    }
+
+    let get = request().method("GET").path("/books").reply(&api).await;
+    let head = request().method("HEAD").path("/books").reply(&api).await;
+    assert_eq!(head.status(), 200);
+    assert_eq!(
+        head.headers()["content-type"],
+        get.headers()["content-type"]
+    );
+
+    let response = request()
+        .method("HEAD")
+        .path("/books/999")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 404);
+
+    let response = request().method("GET").path("/books/abc").reply(&api).await;
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn test_create_book_idempotency_key_reused() {
+    let db_pool = setup_test_db();
+    let api =
+        filters::books(db_pool.clone(), &Default::default()).recover(errors::handle_rejection);
+
+    let new_book = json!({
+        "title": "Test Book",
+        "author": "Test Author",
+        "date_published": "2024-01-01",
+        "cover_image": "http://example.com/cover.jpg"
+    });
+    let other_book = json!({
+        "title": "Other Book",
+        "author": "Test Author",
+        "date_published": "2024-01-01",
+        "cover_image": "http://example.com/cover.jpg"
+    });
+
+    let first = request()
+        .method("POST")
+        .path("/books")
+        .header("Idempotency-Key", "create-test-book")
+        .json(&new_book)
+        .reply(&api)
+        .await;
+    let reused = request()
+        .method("POST")
+        .path("/books")
+        .header("Idempotency-Key", "create-test-book")
+        .json(&other_book)
+        .reply(&api)
+        .await;
+
+    assert_eq!(first.status(), 201);
+    assert_eq!(reused.status(), 422);
+    assert_eq!(
+        db::get_all_books(&mut db_pool.get().unwrap())
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_pool_exhausted_returns_service_unavailable() {
+    let manager = db::ConnectionManager::new(&test_database_url()).unwrap();
+    let pool = r2d2::Pool::builder()
+        .max_size(1)
+        .connection_timeout(Duration::from_millis(100))
+        .build(manager)
+        .expect("Failed to create pool.");
+    let db_pool = Arc::new(db::DbPool::new(
+        pool,
+        db::DEFAULT_QUEUE_SIZE,
+        CircuitBreaker::default(),
+    ));
+    let api =
+        filters::books(db_pool.clone(), &Default::default()).recover(errors::handle_rejection);
+
+    let _held = db_pool.get().unwrap();
+    let response = request().method("GET").path("/books").reply(&api).await;
+
+    assert_eq!(response.status(), 503);
+    assert_eq!(response.headers()["retry-after"], "1");
+}
+
+#[tokio::test]
+async fn test_full_queue_returns_service_unavailable() {
+    let manager = db::ConnectionManager::new(&test_database_url()).unwrap();
+    let pool = r2d2::Pool::builder()
+        .max_size(1)
+        .build(manager)
+        .expect("Failed to create pool.");
+    let db_pool = Arc::new(db::DbPool::new(pool, 0, CircuitBreaker::default()));
+    let api = filters::books(db_pool, &Default::default()).recover(errors::handle_rejection);
+
+    let response = request().method("GET").path("/books").reply(&api).await;
+
+    assert_eq!(response.status(), 503);
+    assert_eq!(response.headers()["retry-after"], "1");
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_fails_fast_after_outage() {
+    let manager = db::ConnectionManager::new(&test_database_url()).unwrap();
+    let pool = r2d2::Pool::builder()
+        .max_size(1)
+        .connection_timeout(Duration::from_millis(100))
+        .build(manager)
+        .expect("Failed to create pool.");
+    let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+    let db_pool = Arc::new(db::DbPool::new(pool, db::DEFAULT_QUEUE_SIZE, breaker));
+    let api =
+        filters::books(db_pool.clone(), &Default::default()).recover(errors::handle_rejection);
+
+    let held = db_pool.get().unwrap();
+    let response = request().method("GET").path("/books").reply(&api).await;
+    assert_eq!(response.status(), 503);
+    drop(held);
+
+    // The pool has recovered, but the open breaker still refuses the request
+    // and asks clients to wait out the rest of its cooldown.
+    let response = request().method("GET").path("/books").reply(&api).await;
+    assert_eq!(response.status(), 503);
+    assert_eq!(response.headers()["retry-after"], "60");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_creates_return_own_book() {
+    let database_url = fresh_database_url();
+    let pool_options = db::PoolOptions {
+        max_size: 4,
+        ..Default::default()
+    };
+    let db_pool = db::create_connection_pool(
+        &database_url,
+        &pool_options,
+        &db::ConnectionOptions::default(),
+    )
+    .expect("Failed to create pool.");
+    db::run_migrations(&mut db_pool.get().unwrap()).expect("Failed to run migrations");
+    let db_pool = Arc::new(db_pool);
+    let api =
+        filters::books(db_pool.clone(), &Default::default()).recover(errors::handle_rejection);
+
+    let clients = (0..16).map(|i| {
+        let api = api.clone();
+        tokio::spawn(async move {
+            let title = format!("Concurrent Book {}", i);
+            let new_book = models::NewBook {
+                title: title.clone(),
+                author: "Test Author".to_string(),
+                date_published: "2024-01-01".to_string(),
+                cover_image: "http://example.com/cover.jpg".to_string(),
+            };
+
+            let response = request()
+                .method("POST")
+                .path("/books")
+                .json(&new_book)
+                .reply(&api)
+                .await;
+
+            assert_eq!(response.status(), 201);
+            let book: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(book["title"], title);
+            book["id"].as_i64().unwrap()
+        })
+    });
+
+    let mut ids = Vec::new();
+    for client in clients {
+        ids.push(client.await.unwrap());
+    }
+    ids.sort_unstable();
+    ids.dedup();
+
+    assert_eq!(ids.len(), 16);
+    assert_eq!(
+        db::get_all_books(&mut db_pool.get().unwrap())
+            .unwrap()
+            .len(),
+        16
+    );
+
+    drop(db_pool);
+    remove_database(&database_url);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_retries_replay_one_book() {
+    let database_url = fresh_database_url();
+    let pool_options = db::PoolOptions {
+        max_size: 4,
+        ..Default::default()
+    };
+    let db_pool = db::create_connection_pool(
+        &database_url,
+        &pool_options,
+        &db::ConnectionOptions::default(),
+    )
+    .expect("Failed to create pool.");
+    db::run_migrations(&mut db_pool.get().unwrap()).expect("Failed to run migrations");
+    let db_pool = Arc::new(db_pool);
+    let api =
+        filters::books(db_pool.clone(), &Default::default()).recover(errors::handle_rejection);
+
+    let start = Arc::new(tokio::sync::Barrier::new(16));
+    let clients = (0..16)
+        .map(|_| {
+            let api = api.clone();
+            let start = start.clone();
+            tokio::spawn(async move {
+                start.wait().await;
+                request()
+                    .method("POST")
+                    .path("/books")
+                    .header("Idempotency-Key", "retried-book")
+                    .json(&json!({
+                        "title": "Retried Book",
+                        "author": "Test Author",
+                        "date_published": "2024-01-01",
+                        "cover_image": ""
+                    }))
+                    .reply(&api)
+                    .await
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut responses = Vec::new();
+    for client in clients {
+        responses.push(client.await.unwrap());
+    }
+    for response in &responses {
+        assert_eq!(response.status(), 201);
+        assert_eq!(response.body(), responses[0].body());
+    }
+    let fresh = responses
+        .iter()
+        .filter(|response| !response.headers().contains_key("idempotent-replayed"))
+        .count();
+    assert_eq!(fresh, 1);
+    assert_eq!(
+        db::get_all_books(&mut db_pool.get().unwrap())
+            .unwrap()
+            .len(),
+        1
+    );
+
+    drop(db_pool);
+    remove_database(&database_url);
+}
+
+#[tokio::test]
+async fn test_database_url_selects_backend() {
+    use db::{Backend, DbConnection};
+
+    #[cfg(feature = "sqlite")]
+    {
+        assert_eq!(Backend::from_url("books.db"), Ok(Backend::Sqlite));
+        assert_eq!(Backend::from_url(":memory:"), Ok(Backend::Sqlite));
+    }
+    #[cfg(not(feature = "sqlite"))]
+    assert!(Backend::from_url("books.db").is_err());
+    #[cfg(feature = "postgres")]
+    assert_eq!(
+        Backend::from_url("postgresql://localhost/books"),
+        Ok(Backend::Postgres)
+    );
+    #[cfg(not(feature = "postgres"))]
+    assert!(Backend::from_url("postgres://localhost/books").is_err());
+
+    // One binary serves whichever backend each URL names.
+    let mut urls = Vec::new();
+    if cfg!(feature = "sqlite") {
+        urls.push(":memory:".to_string());
+    }
+    if testing_postgres() {
+        urls.push(fresh_database_url());
+    }
+    for url in urls {
+        let db_pool = db::create_connection_pool(
+            &url,
+            &db::PoolOptions::default(),
+            &db::ConnectionOptions::default(),
+        )
+        .expect("Failed to create pool.");
+        let conn = &mut db_pool.get().unwrap();
+        match &**conn {
+            #[cfg(feature = "sqlite")]
+            DbConnection::Sqlite(_) => assert!(!url.starts_with("postgres://")),
+            #[cfg(feature = "postgres")]
+            DbConnection::Postgres(_) => assert!(url.starts_with("postgres://")),
+        }
+        db::run_migrations(conn).expect("Failed to run migrations");
+        assert_eq!(db::pending_migrations(conn).unwrap(), 0);
+        let book = db::create_book(
+            conn,
+            models::NewBook {
+                title: "Either Backend".to_string(),
+                author: "Test Author".to_string(),
+                date_published: "2024-01-01".to_string(),
+                cover_image: String::new(),
+            },
+        )
+        .unwrap();
+        assert_eq!(db::get_book(conn, book.id).unwrap().title, "Either Backend");
+    }
+}
+
+#[tokio::test]
+async fn test_db_diagnostics() {
+    let db_pool = setup_test_db();
+    let api = filters::diagnostics(db_pool);
+
+    let response = request()
+        .method("GET")
+        .path("/diagnostics/db")
+        .reply(&api)
+        .await;
+
+    assert_eq!(response.status(), 200);
+    let diagnostics: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    if !testing_postgres() {
+        assert_eq!(diagnostics["pool"]["max_size"], 1);
+        assert_eq!(diagnostics["pragmas"]["busy_timeout_ms"], 5000);
+        assert_eq!(diagnostics["pragmas"]["foreign_keys"], true);
+        assert_eq!(diagnostics["pragmas"]["synchronous"], "NORMAL");
+        assert_eq!(diagnostics["pragmas"]["cache_size"], -64000);
+        assert!(diagnostics.get("settings").is_none());
+    } else {
+        assert_eq!(diagnostics["settings"]["statement_timeout"], "30s");
+        assert_eq!(diagnostics["settings"]["lock_timeout"], "5s");
+    }
+}
+
+#[tokio::test]
+async fn test_in_memory_repository_crud() {
+    let api = filters::books(Arc::new(InMemoryBookRepository::new()), &Default::default())
+        .recover(errors::handle_rejection);
+
+    let new_book = models::NewBook {
+        title: "Test Book".to_string(),
+        author: "Test Author".to_string(),
+        date_published: "2024-01-01".to_string(),
+        cover_image: "http://example.com/cover.jpg".to_string(),
+    };
+    let response = request()
+        .method("POST")
+        .path("/books")
+        .json(&new_book)
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 201);
+    let book: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    let book_path = format!("/books/{}", book["id"]);
+
+    let updated_book = models::NewBook {
+        title: "Updated Test Book".to_string(),
+        ..new_book
+    };
+    let response = request()
+        .method("PUT")
+        .path(&book_path)
+        .json(&updated_book)
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let response = request().method("GET").path(&book_path).reply(&api).await;
+    assert_eq!(response.status(), 200);
+    let book: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(book["title"], "Updated Test Book");
+
+    let response = request().method("GET").path("/books").reply(&api).await;
+    let books: Vec<serde_json::Value> = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(books.len(), 1);
+
+    let response = request()
+        .method("DELETE")
+        .path(&book_path)
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 204);
+
+    let response = request().method("GET").path(&book_path).reply(&api).await;
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn test_in_memory_repository_idempotency() {
+    let api = filters::books(Arc::new(InMemoryBookRepository::new()), &Default::default())
+        .recover(errors::handle_rejection);
+
+    let new_book = json!({
+        "title": "Test Book",
+        "author": "Test Author",
+        "date_published": "2024-01-01",
+        "cover_image": "http://example.com/cover.jpg"
+    });
+    let other_book = json!({
+        "title": "Other Book",
+        "author": "Test Author",
+        "date_published": "2024-01-01",
+        "cover_image": "http://example.com/cover.jpg"
+    });
+
+    let mut responses = Vec::new();
+    for body in [&new_book, &new_book, &other_book] {
+        let response = request()
+            .method("POST")
+            .path("/books")
+            .header("Idempotency-Key", "create-test-book")
+            .json(body)
+            .reply(&api)
+            .await;
+        responses.push(response);
+    }
+
+    assert_eq!(responses[0].status(), 201);
+    assert_eq!(responses[1].status(), 201);
+    assert_eq!(responses[1].headers()["idempotent-replayed"], "true");
+    assert_eq!(responses[0].body(), responses[1].body());
+    assert_eq!(responses[2].status(), 422);
+}
+
+#[test]
+fn test_config_precedence_file_env_cli() {
+    let path = std::env::temp_dir().join(format!("books-config-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        r#"
+        [server]
+        port = 9000
+        bind_address = "127.0.0.1"
+
+        [database.pool]
+        max_size = 4
+        connection_timeout_ms = 1500
+
+        [log]
+        level = "debug"
+        "#,
+    )
+    .unwrap();
+
+    let env = |name: &str| match name {
+        "PORT" => Some("9001".to_string()),
+        "DB_POOL_MAX_SIZE" => Some("6".to_string()),
+        #[cfg(feature = "sqlite")]
+        "DATABASE_URL" => Some("books.db".to_string()),
+        #[cfg(not(feature = "sqlite"))]
+        "DATABASE_URL" => Some("postgres://localhost/books".to_string()),
+        _ => None,
+    };
+    let cli = config::Cli {
+        config: Some(path.clone()),
+        port: Some(9002),
+        ..Default::default()
+    };
+
+    let config = config::Config::load(&cli, env).expect("config should load");
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(config.server.port, 9002);
+    assert_eq!(config.server.bind_address.to_string(), "127.0.0.1");
+    assert_eq!(config.database.pool.max_size, 6);
+    assert_eq!(
+        config.database.pool.connection_timeout,
+        Duration::from_millis(1500)
+    );
+    assert_eq!(config.log.level, config::LogLevel::Debug);
+}
+
+#[test]
+fn test_config_reports_invalid_settings() {
+    let env = |name: &str| match name {
+        "PORT" => Some("0".to_string()),
+        "DB_POOL_MAX_SIZE" => Some("2".to_string()),
+        "DB_POOL_MIN_IDLE" => Some("3".to_string()),
+        "CORS_ALLOWED_ORIGINS" => Some("example.com".to_string()),
+        _ => None,
+    };
+
+    let error = config::Config::load(&config::Cli::default(), env)
+        .expect_err("config should be rejected")
+        .to_string();
+
+    assert!(error.contains("server.port"), "{}", error);
+    assert!(error.contains("DATABASE_URL"), "{}", error);
+    assert!(error.contains("min_idle"), "{}", error);
+    assert!(error.contains("\"example.com\""), "{}", error);
+
+    let error = config::Config::load(&config::Cli::default(), |name: &str| {
+        (name == "PORT").then(|| "eighty".to_string())
+    })
+    .expect_err("config should be rejected")
+    .to_string();
+    assert!(error.contains("PORT=\"eighty\""), "{}", error);
+}
+
+#[tokio::test]
+async fn test_body_limit_rejects_large_requests() {
+    let api = filters::body_limit(16, 128)
+        .and(filters::books(
+            Arc::new(InMemoryBookRepository::new()),
+            &Default::default(),
+        ))
+        .recover(errors::handle_rejection);
+
+    let response = request()
+        .method("POST")
+        .path("/books")
+        .json(&json!({"title": "A title that is far too long", "author": "Author"}))
+        .reply(&api)
+        .await;
+
+    assert_eq!(response.status(), 413);
+
+    // A body of undeclared length, as when chunked, is never read.
+    let response = request()
+        .method("POST")
+        .path("/books")
+        .header("content-type", "application/json")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 411);
+
+    // warp's own header rejections are client errors too.
+    let response = request()
+        .method("POST")
+        .path("/books")
+        .header("content-length", "lots")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+    let response = request()
+        .method("POST")
+        .path("/books")
+        .header("content-length", "0")
+        .header("content-type", "application/json")
+        .header(
+            "idempotency-key",
+            warp::http::HeaderValue::from_bytes(b"\xff").unwrap(),
+        )
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+
+    // Imports have a limit of their own.
+    let response = request()
+        .method("POST")
+        .path("/books/import")
+        .header("content-type", "text/csv")
+        .body("title,author,date_published,cover_image\r\nA title,Author,2024-01-01,\r\n")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let response = request()
+        .method("POST")
+        .path("/books/import")
+        .header("content-type", "text/csv")
+        .body("title,author\r\n".repeat(10))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 413);
+}
+
+fn cors_books_api() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let config = config::CorsConfig {
+        allowed_origins: vec![
+            "https://*.example.com".to_string(),
+            "http://localhost:3000".to_string(),
+        ],
+        allow_credentials: true,
+        ..Default::default()
+    };
+    cors::wrap(
+        Arc::new(cors::Cors::new(&config).unwrap()),
+        filters::books(Arc::new(InMemoryBookRepository::new()), &Default::default())
+            .recover(errors::handle_rejection),
+    )
+}
+
+#[tokio::test]
+async fn test_cors_preflight_for_put_with_json_body() {
+    let api = cors_books_api();
+
+    let response = request()
+        .method("OPTIONS")
+        .path("/books/1")
+        .header("origin", "https://app.example.com")
+        .header("access-control-request-method", "PUT")
+        .header("access-control-request-headers", "Content-Type")
+        .reply(&api)
+        .await;
+
+    assert_eq!(response.status(), 204);
+    let headers = response.headers();
+    assert_eq!(
+        headers["access-control-allow-origin"],
+        "https://app.example.com"
+    );
+    assert_eq!(headers["access-control-allow-credentials"], "true");
+    assert!(headers["access-control-allow-methods"]
+        .to_str()
+        .unwrap()
+        .contains("PUT"));
+    assert!(headers["access-control-allow-headers"]
+        .to_str()
+        .unwrap()
+        .contains("content-type"));
+    assert_eq!(headers["access-control-max-age"], "600");
+    assert_eq!(headers["vary"], "origin");
+}
+
+#[tokio::test]
+async fn test_cors_preflight_rejects_disallowed_requests() {
+    let api = cors_books_api();
+
+    for (origin, method, headers) in [
+        ("https://example.com", "DELETE", "content-type"),
+        ("https://app.example.org", "DELETE", "content-type"),
+        ("http://localhost:3000", "PATCH", "content-type"),
+        ("http://localhost:3000", "DELETE", "x-unknown"),
+    ] {
+        let response = request()
+            .method("OPTIONS")
+            .path("/books/1")
+            .header("origin", origin)
+            .header("access-control-request-method", method)
+            .header("access-control-request-headers", headers)
+            .reply(&api)
+            .await;
+
+        assert_eq!(response.status(), 403, "{} {} {}", origin, method, headers);
+        assert!(!response
+            .headers()
+            .contains_key("access-control-allow-origin"));
+    }
+}
+
+#[tokio::test]
+async fn test_cors_headers_on_actual_requests() {
+    let api = cors_books_api();
+
+    let response = request()
+        .method("GET")
+        .path("/books/42")
+        .header("origin", "http://localhost:3000")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 404);
+    assert_eq!(
+        response.headers()["access-control-allow-origin"],
+        "http://localhost:3000"
+    );
+    assert!(response.headers()["access-control-expose-headers"]
+        .to_str()
+        .unwrap()
+        .contains("retry-after"));
+
+    let response = request()
+        .method("GET")
+        .path("/books")
+        .header("origin", "https://evil.test")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    assert!(!response
+        .headers()
+        .contains_key("access-control-allow-origin"));
+
+    let config = config::CorsConfig {
+        allow_credentials: true,
+        ..Default::default()
+    };
+    assert!(cors::Cors::new(&config).is_err());
+}
+
+#[tokio::test]
+async fn test_graceful_shutdown_drains_in_flight_requests() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let slow = warp::path("slow").then(|| async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        "done"
+    });
+    let shutdown = Arc::new(Shutdown::new());
+    let (bound_tx, bound_rx) = tokio::sync::oneshot::channel();
+
+    let server = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown::serve(
+                slow,
+                ([127, 0, 0, 1], 0),
+                &shutdown,
+                Duration::ZERO,
+                Duration::from_secs(5),
+                |address| bound_tx.send(address).unwrap(),
+            )
+            .await
+        }
+    });
+    let address = bound_rx.await.unwrap();
+
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    stream
+        .write_all(b"GET /slow HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    shutdown.trigger();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("done"), "{}", response);
+
+    assert!(server.await.unwrap(), "server should drain in time");
+    assert!(tokio::net::TcpStream::connect(address).await.is_err());
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_checkpoint_wal_truncates_log() {
+    let database_url = fresh_sqlite_database_url();
+    let db_pool = db::create_connection_pool(
+        &database_url,
+        &db::PoolOptions::default(),
+        &db::ConnectionOptions::default(),
+    )
+    .expect("Failed to create pool.");
+    db::run_migrations(&mut db_pool.get().unwrap()).expect("Failed to run migrations");
+    let wal = format!("{}-wal", database_url);
+    assert!(std::fs::metadata(&wal).unwrap().len() > 0);
+
+    db_pool.run(db::checkpoint_wal).await.unwrap();
+
+    assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+    drop(db_pool);
+    remove_database(&database_url);
+}
+
+#[tokio::test]
+async fn test_health_endpoints() {
+    let shutdown = Arc::new(Shutdown::new());
+    let api = filters::health(setup_test_db(), shutdown.clone());
+
+    let response = request().method("GET").path("/healthz").reply(&api).await;
+    assert_eq!(response.status(), 200);
+
+    let response = request().method("GET").path("/readyz").reply(&api).await;
+    assert_eq!(response.status(), 200);
+
+    let response = request().method("GET").path("/health").reply(&api).await;
+    assert_eq!(response.status(), 200);
+    let report: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(report["status"], "up");
+    let database = report["components"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|component| component["name"] == "database")
+        .unwrap();
+    assert_eq!(database["status"], "up");
+    assert!(database["latency_ms"].as_f64().is_some());
+
+    shutdown.trigger();
+
+    let response = request().method("GET").path("/readyz").reply(&api).await;
+    assert_eq!(response.status(), 503);
+    let response = request().method("GET").path("/healthz").reply(&api).await;
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn test_pre_stop_delay_keeps_accepting_while_not_ready() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let shutdown = Arc::new(Shutdown::new());
+    let api = filters::health(setup_test_db(), shutdown.clone());
+    let (bound_tx, bound_rx) = tokio::sync::oneshot::channel();
+
+    let server = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown::serve(
+                api,
+                ([127, 0, 0, 1], 0),
+                &shutdown,
+                Duration::from_millis(500),
+                Duration::from_secs(5),
+                |address| bound_tx.send(address).unwrap(),
+            )
+            .await
+        }
+    });
+    let address = bound_rx.await.unwrap();
+    shutdown.trigger();
+
+    // A new connection during the delay is served, and told to go elsewhere.
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    stream
+        .write_all(b"GET /readyz HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+
+    assert!(server.await.unwrap(), "server should drain in time");
+    assert!(tokio::net::TcpStream::connect(address).await.is_err());
+}
+
+#[tokio::test]
+async fn test_readyz_fails_with_pending_migrations() {
+    let db_pool = db::create_connection_pool(
+        &test_database_url(),
+        &db::PoolOptions::default(),
+        &db::ConnectionOptions::default(),
+    )
+    .expect("Failed to create pool.");
+    let api = filters::health(Arc::new(db_pool), Arc::new(Shutdown::new()));
+
+    let response = request().method("GET").path("/readyz").reply(&api).await;
+    assert_eq!(response.status(), 503);
+
+    let response = request().method("GET").path("/health").reply(&api).await;
+    let report: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    let migrations = report["components"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|component| component["name"] == "migrations")
+        .unwrap();
+    assert_eq!(migrations["status"], "down");
+}
+
+#[tokio::test]
+async fn test_metrics_label_requests_by_route_template() {
+    let api = metrics::instrument(
+        &["/books", "/books/{id}"],
+        filters::books(Arc::new(InMemoryBookRepository::new()), &Default::default())
+            .recover(errors::handle_rejection),
+    );
+
+    let response = request().method("GET").path("/books/7").reply(&api).await;
+    assert_eq!(response.status(), 404);
+    request()
+        .method("GET")
+        .path("/no/such/path")
+        .reply(&api)
+        .await;
+
+    let text = metrics::render();
+    assert!(
+        text.contains(r#"http_requests_total{method="GET",route="/books/{id}",status="404"}"#),
+        "{}",
+        text
+    );
+    assert!(text.contains(r#"route="unmatched""#), "{}", text);
+    assert!(!text.contains("/books/7"), "{}", text);
+    assert!(text.contains("http_request_duration_seconds_bucket"));
+}
+
+#[tokio::test]
+async fn test_metrics_endpoint() {
+    let db_pool = setup_test_db();
+    db::create_book(
+        &mut db_pool.get().unwrap(),
+        models::NewBook {
+            title: "Metrics Book".to_string(),
+            author: "Test Author".to_string(),
+            date_published: "2024-01-01".to_string(),
+            cover_image: "http://example.com/cover.jpg".to_string(),
+        },
+    )
+    .unwrap();
+    let api = filters::metrics(db_pool.clone(), db_pool);
+
+    let response = request().method("GET").path("/metrics").reply(&api).await;
+
+    assert_eq!(response.status(), 200);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let text = std::str::from_utf8(response.body()).unwrap();
+    assert!(text.contains("\nbooks "), "{}", text);
+    assert!(text.contains("db_pool_connections "), "{}", text);
+    assert!(text.contains("db_pool_wait_seconds_count"), "{}", text);
+    assert!(
+        text.contains(r#"db_query_duration_seconds_count{function="count_books"}"#),
+        "{}",
+        text
+    );
+}
+
+#[tokio::test]
+async fn test_request_id_is_propagated_and_included_in_errors() {
+    let api = request_id::wrap(
+        &["/books", "/books/{id}"],
+        filters::books(Arc::new(InMemoryBookRepository::new()), &Default::default())
+            .recover(errors::handle_rejection),
+    );
+
+    let response = request()
+        .method("GET")
+        .path("/books/42")
+        .header("x-request-id", "support-ticket-123")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 404);
+    assert_eq!(response.headers()["x-request-id"], "support-ticket-123");
+    let body: models::ErrorBody = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body.status, 404);
+    assert_eq!(body.error, "Not Found");
+    assert_eq!(body.request_id.as_deref(), Some("support-ticket-123"));
+
+    let response = request().method("GET").path("/books").reply(&api).await;
+    assert_eq!(response.status(), 200);
+    let generated = response.headers()["x-request-id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(generated).is_ok(), "{}", generated);
+
+    let response = request()
+        .method("GET")
+        .path("/books")
+        .header("x-request-id", "has spaces")
+        .reply(&api)
+        .await;
+    assert_ne!(response.headers()["x-request-id"], "has spaces");
+}
+
+#[tokio::test]
+async fn test_compressed_errors_include_request_id() {
+    let compression = compression::Compression::new(&config::CompressionConfig {
+        min_size_bytes: 0,
+        ..Default::default()
+    });
+    // Wrapped as in `serve`.
+    let api = compression::wrap(
+        Some(Arc::new(compression)),
+        request_id::wrap(
+            &["/books", "/books/{id}"],
+            filters::books(Arc::new(InMemoryBookRepository::new()), &Default::default())
+                .recover(errors::handle_rejection),
+        ),
+    );
+
+    let response = request()
+        .path("/books/42")
+        .header("x-request-id", "support-ticket-123")
+        .header("accept-encoding", "gzip")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 404);
+    assert_eq!(response.headers()["content-encoding"], "gzip");
+    assert_eq!(
+        response.headers()["content-length"],
+        response.body().len().to_string()
+    );
+    let mut inflated = Vec::new();
+    std::io::Read::read_to_end(
+        &mut flate2::read::GzDecoder::new(&response.body()[..]),
+        &mut inflated,
+    )
+    .unwrap();
+    let body: models::ErrorBody = serde_json::from_slice(&inflated).unwrap();
+    assert_eq!(body.request_id.as_deref(), Some("support-ticket-123"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_traces_are_exported_over_otlp() {
+    use opentelemetry::trace::TracerProvider;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    // A mock OTLP/HTTP collector that hands over every export request body.
+    let (exports, mut exported) = tokio::sync::mpsc::unbounded_channel();
+    let collector = warp::post()
+        .and(warp::path!("v1" / "traces"))
+        .and(warp::body::bytes())
+        .map(move |body: warp::hyper::body::Bytes| {
+            let _ = exports.send(body.to_vec());
+            warp::reply()
+        });
+    let (collector_address, collector) = warp::serve(collector).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(collector);
+
+    let provider = telemetry::tracer_provider(
+        &format!("http://{}", collector_address),
+        config::OtlpProtocol::HttpProtobuf,
+        "books-test",
+    )
+    .unwrap();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    let _subscriber = tracing::subscriber::set_default(subscriber);
+
+    let api = telemetry::wrap(
+        &["/books", "/books/{id}"],
+        filters::books(setup_test_db(), &Default::default()).recover(errors::handle_rejection),
+    );
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+    let response = request()
+        .method("GET")
+        .path("/books")
+        .header(
+            "traceparent",
+            format!("00-{}-00f067aa0ba902b7-01", trace_id),
+        )
+        .reply(&api)
+        .await;
+
+    assert_eq!(response.status(), 200);
+    let traceparent = response.headers()["traceparent"].to_str().unwrap();
+    assert!(
+        traceparent.starts_with(&format!("00-{}-", trace_id)),
+        "{}",
+        traceparent
+    );
+    assert!(!traceparent.contains("00f067aa0ba902b7"), "{}", traceparent);
+
+    tokio::task::spawn_blocking(move || provider.force_flush())
+        .await
+        .unwrap()
+        .unwrap();
+    // Spans may arrive split over several export requests.
+    let trace_id = hex::decode(trace_id).unwrap();
+    let needles: [&[u8]; 4] = [
+        &trace_id,
+        b"GET /books",
+        b"db get_books_page",
+        b"books-test",
+    ];
+    let mut body = Vec::new();
+    let contains =
+        |body: &[u8], needle: &[u8]| body.windows(needle.len()).any(|window| window == needle);
+    while !needles.iter().all(|needle| contains(&body, needle)) {
+        let export = tokio::time::timeout(Duration::from_secs(5), exported.recv())
+            .await
+            .unwrap_or_else(|_| panic!("missing spans in {:?}", String::from_utf8_lossy(&body)))
+            .unwrap();
+        body.extend(export);
+    }
+}
+
+/// Returns a new, empty directory for log files.
+fn fresh_log_dir() -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "books-test-logs-{}-{}",
+        std::process::id(),
+        NEXT_DATABASE.fetch_add(1, Ordering::SeqCst)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[tokio::test]
+async fn test_access_log_writes_combined_format() {
+    let path = fresh_log_dir().join("access.log");
+    let log = Arc::new(AccessLog::open(&path, &config::AccessLogConfig::default()).unwrap());
+    let api = crate::access_log::wrap(
+        Some(log.clone()),
+        filters::books(Arc::new(InMemoryBookRepository::new()), &Default::default())
+            .recover(errors::handle_rejection),
+    );
+
+    request()
+        .method("GET")
+        .path("/books?page=1")
+        .remote_addr("192.0.2.7:50000".parse().unwrap())
+        .header("referer", "https://example.com/")
+        .header("user-agent", "curl/8.5 \"quoted\"")
+        .reply(&api)
+        .await;
+    request().method("GET").path("/books/42").reply(&api).await;
+    // Headers that are not UTF-8 are still logged, escaped byte by byte.
+    request()
+        .method("GET")
+        .path("/books")
+        .header(
+            "user-agent",
+            warp::http::HeaderValue::from_bytes(b"curl/8.5 \xff").unwrap(),
+        )
+        .reply(&api)
+        .await;
+    log.flush();
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let lines = contents.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3, "{}", contents);
+    assert!(lines[0].starts_with("192.0.2.7 - - ["), "{}", lines[0]);
+    assert!(
+        lines[0].ends_with(
+            r#"+0000] "GET /books?page=1 HTTP/1.1" 200 - "https://example.com/" "curl/8.5 \"quoted\"""#
+        ),
+        "{}",
+        lines[0]
+    );
+    assert!(
+        lines[1].starts_with("- - - [")
+            && lines[1].ends_with(r#""GET /books/42 HTTP/1.1" 404 34 "-" "-""#),
+        "{}",
+        lines[1]
+    );
+    assert!(
+        lines[2].ends_with(r#""GET /books HTTP/1.1" 200 - "-" "curl/8.5 \xff""#),
+        "{}",
+        lines[2]
+    );
+}
+
+#[test]
+fn test_rotating_file_rotates_by_size_and_time_and_prunes() {
+    use time::macros::datetime;
+
+    let dir = fresh_log_dir();
+    let path = dir.join("access.log");
+    let mut file = RotatingFile::open(&path, config::Rotation::Daily, 20, 2).unwrap();
+
+    let morning = datetime!(2026-10-18 09:00:00 UTC);
+    file.write_line("first line", morning).unwrap();
+    // Would exceed 20 bytes, so "first line" moves to a rotated file.
+    file.write_line("second line", morning).unwrap();
+    assert_eq!(file.rotated_files().unwrap().len(), 1);
+
+    // A new day starts a new file even though this one has room.
+    file.write_line("x", datetime!(2026-10-19 00:00:01 UTC))
+        .unwrap();
+    file.write_line("y", datetime!(2026-10-20 00:00:01 UTC))
+        .unwrap();
+
+    let mut rotated = file.rotated_files().unwrap();
+    rotated.sort();
+    let names = rotated
+        .iter()
+        .map(|path| path.file_name().unwrap().to_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        ["access.log.20261019T000001", "access.log.20261020T000001"]
+    );
+    assert_eq!(
+        std::fs::read_to_string(&rotated[0]).unwrap(),
+        "second line\n"
+    );
+    assert_eq!(std::fs::read_to_string(&rotated[1]).unwrap(), "x\n");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "y\n");
+
+    // Files that merely share the prefix are never pruned.
+    std::fs::write(dir.join("access.log.old"), "keep").unwrap();
+    assert_eq!(file.rotated_files().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_docs_assets_are_served_with_caching_headers() {
+    static ASSETS: &[Asset] = &[Asset {
+        name: "app.js",
+        content_type: "text/javascript; charset=utf-8",
+        bytes: b"console.log('docs');",
+    }];
+    let api = docs_assets::serve(ASSETS);
+
+    let response = request()
+        .method("GET")
+        .path("/docs/assets/app.js")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/javascript; charset=utf-8"
+    );
+    assert_eq!(response.headers()["cache-control"], "public, max-age=86400");
+    assert_eq!(response.body(), "console.log('docs');");
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+
+    let response = request()
+        .method("GET")
+        .path("/docs/assets/app.js")
+        .header("if-none-match", format!("\"other\", {}", etag))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 304);
+    assert!(response.body().is_empty());
+    assert_eq!(response.headers()["etag"], etag.as_str());
+
+    let response = request()
+        .method("GET")
+        .path("/docs/assets/missing.js")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn test_docs_pages_load_bundled_or_cdn_assets() {
+    let bundled = docs_assets::Urls::new(config::DocsAssets::Bundled);
+    let cdn = docs_assets::Urls::new(config::DocsAssets::Cdn);
+
+    let page = |urls| async move {
+        let swagger_ui = request()
+            .method("GET")
+            .path("/docs")
+            .reply(&crate::serve_swagger_ui(&urls))
+            .await;
+        let redoc = request()
+            .method("GET")
+            .path("/redoc")
+            .reply(&crate::serve_redoc_ui(&urls))
+            .await;
+        format!(
+            "{}{}",
+            String::from_utf8_lossy(swagger_ui.body()),
+            String::from_utf8_lossy(redoc.body())
+        )
+    };
+
+    let html = page(bundled).await;
+    assert!(html.contains(r#"src="/docs/assets/swagger-ui-bundle.js""#));
+    assert!(html.contains(r#"href="/docs/assets/swagger-ui.css""#));
+    assert!(html.contains(r#"src="/docs/assets/redoc.standalone.js""#));
+    assert!(!html.contains("https://"), "{}", html);
+
+    // The bundled assets are embedded, whatever the build features.
+    for asset in docs_assets::BUNDLED {
+        let response = request()
+            .method("GET")
+            .path(&format!("/docs/assets/{}", asset.name))
+            .reply(&docs_assets::serve(docs_assets::BUNDLED))
+            .await;
+        assert_eq!(response.status(), 200, "{}", asset.name);
+        assert!(response.body().len() > 1000, "{}", asset.name);
+    }
+
+    let html = page(cdn).await;
+    assert!(html.contains("https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui-bundle.js"));
+    assert!(html.contains("https://cdn.redoc.ly/redoc/v2.0.0-rc.72/bundles/redoc.standalone.js"));
+    assert!(html.contains("https://fonts.googleapis.com/"));
+}
+
+/// Checks `instance` against an OpenAPI 3.0 `schema` from `spec`, rejecting
+/// undocumented properties so that the document cannot fall behind the code.
+fn check_schema(
+    spec: &serde_json::Value,
+    schema: &serde_json::Value,
+    instance: &serde_json::Value,
+    at: &str,
+) -> Result<(), String> {
+    use serde_json::Value;
+
+    if let Some(reference) = schema["$ref"].as_str() {
+        let target = spec
+            .pointer(reference.trim_start_matches('#'))
+            .ok_or_else(|| format!("{}: unresolved {}", at, reference))?;
+        return check_schema(spec, target, instance, at);
+    }
+    if instance.is_null() && schema["nullable"] == true {
+        return Ok(());
+    }
+    if let Some(all_of) = schema["allOf"].as_array() {
+        return all_of
+            .iter()
+            .try_for_each(|schema| check_schema(spec, schema, instance, at));
+    }
+    if let Some(values) = schema["enum"].as_array() {
+        if !values.contains(instance) {
+            return Err(format!("{}: {} is not one of {:?}", at, instance, values));
+        }
+    }
+
+    let matches_type = match (schema["type"].as_str(), instance) {
+        (None, _) => true,
+        (Some("object"), Value::Object(object)) => {
+            let properties = schema["properties"].as_object();
+            for name in schema["required"].as_array().into_iter().flatten() {
+                let name = name.as_str().unwrap();
+                if !object.contains_key(name) {
+                    return Err(format!("{}: missing required {:?}", at, name));
+                }
+            }
+            for (name, value) in object {
+                let property = properties
+                    .and_then(|properties| properties.get(name))
+                    .ok_or_else(|| format!("{}: undocumented property {:?}", at, name))?;
+                check_schema(spec, property, value, &format!("{}.{}", at, name))?;
+            }
+            true
+        }
+        (Some("array"), Value::Array(items)) => {
+            for (i, item) in items.iter().enumerate() {
+                check_schema(spec, &schema["items"], item, &format!("{}[{}]", at, i))?;
+            }
+            true
+        }
+        (Some("string"), Value::String(_)) => true,
+        (Some("integer"), Value::Number(n)) => n.is_i64() || n.is_u64(),
+        (Some("number"), Value::Number(_)) => true,
+        (Some("boolean"), Value::Bool(_)) => true,
+        _ => false,
+    };
+    if matches_type {
+        Ok(())
+    } else {
+        Err(format!(
+            "{}: {} is not of type {}",
+            at, instance, schema["type"]
+        ))
+    }
+}
+
+/// Asserts that the OpenAPI document lists `response` for `method` on `path` and describes its body.
+fn assert_documented(
+    spec: &serde_json::Value,
+    method: &str,
+    path: &str,
+    response: &warp::http::Response<warp::hyper::body::Bytes>,
+) {
+    let status = response.status().as_str().to_string();
+    let documented = &spec["paths"][path][method]["responses"][&status];
+    assert!(
+        documented.is_object(),
+        "{} {} does not document {}",
+        method,
+        path,
+        status
+    );
+
+    match documented["content"].as_object() {
+        None => assert!(
+            response.body().is_empty(),
+            "{} {} {}: undocumented body {:?}",
+            method,
+            path,
+            status,
+            response.body()
+        ),
+        Some(content) => {
+            let content_type = response.headers()["content-type"].to_str().unwrap();
+            let media = content
+                .get(content_type.split(';').next().unwrap())
+                .unwrap_or_else(|| {
+                    panic!(
+                        "{} {} {}: undocumented {}",
+                        method, path, status, content_type
+                    )
+                });
+            let body = serde_json::from_slice(response.body()).unwrap();
+            if let Err(e) = check_schema(spec, &media["schema"], &body, "body") {
+                panic!("{} {} {}: {}", method, path, status, e);
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_books_responses_match_openapi_document() {
+    let spec = serde_json::to_value(<crate::ApiDocs as utoipa::OpenApi>::openapi()).unwrap();
+    let api = request_id::wrap(
+        &["/books", "/books/{id}"],
+        filters::books(setup_test_db(), &Default::default()).recover(errors::handle_rejection),
+    );
+    let book = json!({
+        "title": "Spec Book",
+        "author": "Test Author",
+        "date_published": "2024-01-01",
+        "cover_image": "http://example.com/cover.jpg"
+    });
+
+    let response = request().method("GET").path("/books").reply(&api).await;
+    assert_documented(&spec, "get", "/v1/books", &response);
+
+    let created = request()
+        .method("POST")
+        .path("/books")
+        .header("Idempotency-Key", "spec-book")
+        .json(&book)
+        .reply(&api)
+        .await;
+    assert_eq!(created.status(), 201);
+    assert_documented(&spec, "post", "/v1/books", &created);
+    let id = serde_json::from_slice::<models::Book>(created.body())
+        .unwrap()
+        .id;
+    let book_path = format!("/books/{}", id);
+
+    let mut invalid = book.clone();
+    invalid["title"] = json!("");
+    for (key, body) in [
+        (
+            "spec-book",
+            json!({"title": "Other", "author": "A", "date_published": "", "cover_image": ""}),
+        ),
+        ("invalid-book", invalid),
+    ] {
+        let response = request()
+            .method("POST")
+            .path("/books")
+            .header("Idempotency-Key", key)
+            .json(&body)
+            .reply(&api)
+            .await;
+        assert_documented(&spec, "post", "/v1/books", &response);
+    }
+    let response = request()
+        .method("POST")
+        .path("/books")
+        .header("content-type", "application/json")
+        .body("{not json")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+    assert_documented(&spec, "post", "/v1/books", &response);
+    let response = request()
+        .method("POST")
+        .path("/books")
+        .header("content-type", "text/plain")
+        .body("Spec Book")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 415);
+    assert_documented(&spec, "post", "/v1/books", &response);
+
+    let response = request().method("GET").path("/books").reply(&api).await;
+    assert_documented(&spec, "get", "/v1/books", &response);
+    let response = request()
+        .method("GET")
+        .path("/books")
+        .header("accept", "text/html")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 406);
+    assert_documented(&spec, "get", "/v1/books", &response);
+
+    for path in [book_path.as_str(), "/books/999"] {
+        let response = request().method("GET").path(path).reply(&api).await;
+        assert_documented(&spec, "get", "/v1/books/{id}", &response);
+
+        let response = request()
+            .method("PUT")
+            .path(path)
+            .json(&book)
+            .reply(&api)
+            .await;
+        assert_documented(&spec, "put", "/v1/books/{id}", &response);
+
+        let response = request().method("DELETE").path(path).reply(&api).await;
+        assert_documented(&spec, "delete", "/v1/books/{id}", &response);
+    }
+
+    let response = request().method("OPTIONS").path("/books").reply(&api).await;
+    assert_documented(&spec, "options", "/v1/books", &response);
+    for (content_type, body) in [
+        ("application/x-ndjson", format!("{}\n{{}}", book)),
+        ("text/csv", "title,title\r\n".to_string()),
+        ("text/plain", String::new()),
+    ] {
+        let response = request()
+            .method("POST")
+            .path("/books/import")
+            .header("content-type", content_type)
+            .body(body)
+            .reply(&api)
+            .await;
+        assert_documented(&spec, "post", "/v1/books/import", &response);
+    }
+    let response = request()
+        .method("OPTIONS")
+        .path("/books/import")
+        .reply(&api)
+        .await;
+    assert_documented(&spec, "options", "/v1/books/import", &response);
+
+    let response = request()
+        .method("GET")
+        .path("/books/export")
+        .reply(&api)
+        .await;
+    assert_documented(&spec, "get", "/v1/books/export", &response);
+    let response = request()
+        .method("OPTIONS")
+        .path("/books/export")
+        .reply(&api)
+        .await;
+    assert_documented(&spec, "options", "/v1/books/export", &response);
+    let response = request()
+        .method("OPTIONS")
+        .path(&book_path)
+        .reply(&api)
+        .await;
+    assert_documented(&spec, "options", "/v1/books/{id}", &response);
+}
+
+#[tokio::test]
+async fn test_v2_lists_authors_and_v1_is_deprecated() {
+    let spec = serde_json::to_value(<crate::ApiDocsV2 as utoipa::OpenApi>::openapi()).unwrap();
+    let db_pool = setup_test_db();
+    let api = versioning::wrap(
+        warp::path("v1")
+            .and(filters::books(db_pool.clone(), &Default::default()))
+            .or(warp::path("v2").and(filters::v2::books(db_pool, &Default::default())))
+            .recover(errors::handle_rejection),
+    );
+    let book = json!({
+        "title": "Spec Book",
+        "authors": ["Ann Author", "Bob Author"],
+        "date_published": "2024-01-01",
+        "cover_image": "http://example.com/cover.jpg"
+    });
+
+    let created = request()
+        .method("POST")
+        .path("/v2/books")
+        .header("Idempotency-Key", "v2-book")
+        .json(&book)
+        .reply(&api)
+        .await;
+    assert_eq!(created.status(), 201);
+    assert_documented(&spec, "post", "/v2/books", &created);
+    assert!(created.headers().get("deprecation").is_none());
+    let id = serde_json::from_slice::<models::v2::Book>(created.body())
+        .unwrap()
+        .id;
+    assert_eq!(
+        created.headers()["location"],
+        format!("/v2/books/{}", id).as_str()
+    );
+
+    let replay = request()
+        .method("POST")
+        .path("/v2/books")
+        .header("Idempotency-Key", "v2-book")
+        .json(&book)
+        .reply(&api)
+        .await;
+    assert_eq!(replay.headers()["idempotent-replayed"], "true");
+    assert_eq!(replay.body(), created.body());
+
+    let v1 = request()
+        .method("GET")
+        .path(&format!("/v1/books/{}", id))
+        .reply(&api)
+        .await;
+    assert_eq!(
+        serde_json::from_slice::<models::Book>(v1.body())
+            .unwrap()
+            .author,
+        "Ann Author; Bob Author"
+    );
+    assert_eq!(
+        v1.headers()["deprecation"],
+        format!("@{}", versioning::V1_DEPRECATED.unix_timestamp()).as_str()
+    );
+    assert_eq!(v1.headers()["sunset"], "Fri, 30 Apr 2027 00:00:00 GMT");
+    assert_eq!(
+        v1.headers()["link"],
+        format!("</v2/books/{}>; rel=\"successor-version\"", id).as_str()
+    );
+
+    let book_path = format!("/v2/books/{}", id);
+    let response = request().method("GET").path("/v2/books").reply(&api).await;
+    assert_documented(&spec, "get", "/v2/books", &response);
+    for path in [book_path.as_str(), "/v2/books/999"] {
+        let response = request().method("GET").path(path).reply(&api).await;
+        assert_documented(&spec, "get", "/v2/books/{id}", &response);
+
+        let response = request()
+            .method("PUT")
+            .path(path)
+            .json(&book)
+            .reply(&api)
+            .await;
+        assert_documented(&spec, "put", "/v2/books/{id}", &response);
+    }
+
+    for authors in [json!([]), json!([""]), json!(["Ann; Bob"])] {
+        let mut invalid = book.clone();
+        invalid["authors"] = authors;
+        let response = request()
+            .method("POST")
+            .path("/v2/books")
+            .json(&invalid)
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 400);
+        assert_documented(&spec, "post", "/v2/books", &response);
+    }
+
+    let response = request()
+        .method("DELETE")
+        .path(&book_path)
+        .reply(&api)
+        .await;
+    assert_documented(&spec, "delete", "/v2/books/{id}", &response);
+    let response = request()
+        .method("OPTIONS")
+        .path(&book_path)
+        .reply(&api)
+        .await;
+    assert_documented(&spec, "options", "/v2/books/{id}", &response);
+}
+
+#[tokio::test]
+async fn test_sparse_fieldsets_and_expansion() {
+    let db_pool = setup_test_db();
+    let api = filters::books(db_pool.clone(), &Default::default())
+        .or(warp::path("v2").and(filters::v2::books(db_pool, &Default::default())))
+        .recover(errors::handle_rejection);
+    let created = request()
+        .method("POST")
+        .path("/books")
+        .json(&json!({
+            "title": "Test Book",
+            "author": "Test Author",
+            "date_published": "2024-01-01",
+            "cover_image": "http://example.com/cover.jpg"
+        }))
+        .reply(&api)
+        .await;
+    let id = serde_json::from_slice::<models::Book>(created.body())
+        .unwrap()
+        .id;
+
+    let response = request()
+        .method("GET")
+        .path("/books?fields=id,%20title")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let books: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(books, json!([{"id": id, "title": "Test Book"}]));
+
+    let response = request()
+        .method("GET")
+        .path(&format!("/v2/books/{}?fields=authors", id))
+        .reply(&api)
+        .await;
+    let book: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(book, json!({"authors": ["Test Author"]}));
+
+    for query in [
+        "fields=id,isbn",
+        "fields=",
+        "fields=id,,title",
+        "fields=authors",
+        "expand=authors",
+    ] {
+        let response = request()
+            .method("GET")
+            .path(&format!("/books/{}?{}", id, query))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 400, "{}", query);
+        let body: models::ErrorBody = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body.error, "Invalid Query");
+    }
+}
+
+#[test]
+fn test_selectable_fields_match_book_schemas() {
+    let properties = |spec: utoipa::openapi::OpenApi| {
+        let spec = serde_json::to_value(spec).unwrap();
+        let mut names = spec["components"]["schemas"]["Book"]["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    };
+    let sorted = |fields: &[&str]| {
+        let mut fields = fields.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        fields.sort();
+        fields
+    };
+
+    assert_eq!(
+        properties(<crate::ApiDocs as utoipa::OpenApi>::openapi()),
+        sorted(models::Book::FIELDS)
+    );
+    assert_eq!(
+        properties(<crate::ApiDocsV2 as utoipa::OpenApi>::openapi()),
+        sorted(models::v2::Book::FIELDS)
+    );
+}
+
+#[tokio::test]
+async fn test_books_in_other_formats() {
+    let db_pool = setup_test_db();
+    let api = filters::books(db_pool.clone(), &Default::default())
+        .or(warp::path("v2").and(filters::v2::books(db_pool, &Default::default())))
+        .recover(errors::handle_rejection);
+
+    let bodies: [(&str, Vec<u8>); 4] = [
+        (
+            "text/csv",
+            b"title,author,date_published,cover_image\r\n\"Commas, Quotes \"\"\",Test Author,2024-01-01,\r\n"
+                .to_vec(),
+        ),
+        (
+            "application/xml",
+            b"<book><title>Commas, Quotes &quot;</title><author>Test Author</author>\
+              <date_published>2024-01-01</date_published><cover_image/></book>"
+                .to_vec(),
+        ),
+        (
+            "application/yaml",
+            b"title: 'Commas, Quotes \"'\nauthor: Test Author\ndate_published: 2024-01-01\ncover_image: ''\n"
+                .to_vec(),
+        ),
+        (
+            "application/msgpack",
+            crate::msgpack::to_vec(&json!({
+                "title": "Commas, Quotes \"",
+                "author": "Test Author",
+                "date_published": "2024-01-01",
+                "cover_image": ""
+            })),
+        ),
+    ];
+    for (content_type, body) in bodies {
+        let response = request()
+            .method("POST")
+            .path("/books")
+            .header("content-type", content_type)
+            .body(body)
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 201, "{}", content_type);
+        let book: models::Book = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(book.title, "Commas, Quotes \"", "{}", content_type);
+        assert_eq!(book.cover_image, "", "{}", content_type);
+    }
+
+    let response = request()
+        .method("POST")
+        .path("/v2/books")
+        .header("content-type", "text/csv")
+        .body("title,authors,date_published,cover_image\nTwo Authors,A; B,2024-01-01,\n")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 201);
+    let book: models::v2::Book = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(book.authors, ["A", "B"]);
+    let id = book.id;
+
+    let response = request()
+        .method("GET")
+        .path(&format!("/v2/books/{}", id))
+        .header("accept", "text/csv")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/csv; charset=utf-8"
+    );
+    assert_eq!(response.headers()["vary"], "accept");
+    assert_eq!(
+        response.body(),
+        format!(
+            "id,title,authors,date_published,cover_image\r\n{},Two Authors,A; B,2024-01-01,\r\n",
+            id
+        )
+        .as_bytes()
+    );
+
+    let response = request()
+        .method("GET")
+        .path(&format!("/v2/books/{}?fields=id,authors", id))
+        .header("accept", "application/json;q=0.5, application/xml")
+        .reply(&api)
+        .await;
+    assert_eq!(response.headers()["content-type"], "application/xml");
+    assert_eq!(
+        response.body(),
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <book><id>{}</id><authors><author>A</author><author>B</author></authors></book>\n",
+            id
+        )
+        .as_bytes()
+    );
+
+    let response = request()
+        .method("GET")
+        .path("/books?fields=title")
+        .header("accept", "application/msgpack")
+        .reply(&api)
+        .await;
+    assert_eq!(response.headers()["content-type"], "application/msgpack");
+    let books = crate::msgpack::from_slice(response.body()).unwrap();
+    assert_eq!(books.as_array().unwrap().len(), 5);
+    assert_eq!(books[4], json!({"title": "Two Authors"}));
+
+    let response = request()
+        .method("GET")
+        .path(&format!("/books/{}", id))
+        .header("accept", "application/*")
+        .reply(&api)
+        .await;
+    assert_eq!(response.headers()["content-type"], "application/json");
+
+    let response = request()
+        .method("GET")
+        .path("/books")
+        .header("accept", "text/html, application/json;q=0")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 406);
+    let body: models::ErrorBody = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body.error, "Not Acceptable");
+
+    for (content_type, body) in [
+        ("text/plain", "title: Plain"),
+        ("text/csv", "title,author\nA,B\nC,D\n"),
+        ("application/xml", "<!DOCTYPE book><book/>"),
+    ] {
+        let response = request()
+            .method("PUT")
+            .path(&format!("/books/{}", id))
+            .header("content-type", content_type)
+            .body(body)
+            .reply(&api)
+            .await;
+        let expected = if content_type == "text/plain" {
+            415
+        } else {
+            400
+        };
+        assert_eq!(response.status(), expected, "{}", content_type);
+    }
+
+    let spec = serde_json::to_value(<crate::ApiDocsV2 as utoipa::OpenApi>::openapi()).unwrap();
+    for (method, path, content) in [
+        ("get", "/v2/books", "/responses/200/content"),
+        ("post", "/v2/books", "/requestBody/content"),
+        ("put", "/v2/books/{id}", "/requestBody/content"),
+    ] {
+        let content = spec["paths"][path][method].pointer(content).unwrap();
+        for media_type in [
+            "application/json",
+            "text/csv",
+            "application/xml",
+            "application/yaml",
+            "application/msgpack",
+        ] {
+            assert!(
+                content.get(media_type).is_some(),
+                "{} {} lacks {}",
+                method,
+                path,
+                media_type
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_book_lists_stream_page_by_page() {
+    use crate::repository::BookRepository;
+    use crate::streaming::PAGE_SIZE;
+
+    let repo = Arc::new(InMemoryBookRepository::new());
+    let count = 2 * PAGE_SIZE as i32 + 1;
+    for i in 1..=count {
+        let book = models::NewBook {
+            title: format!("Book {}", i),
+            author: "Test Author".to_string(),
+            date_published: "2024-01-01".to_string(),
+            cover_image: String::new(),
+        };
+        repo.create(book, None).await.unwrap();
+    }
+    repo.delete(PAGE_SIZE as i32).await.unwrap();
+    let api = filters::books(repo.clone(), &Default::default())
+        .or(warp::path("v2").and(filters::v2::books(repo, &Default::default())))
+        .recover(errors::handle_rejection);
+    let ids = (1..=count)
+        .filter(|&id| id != PAGE_SIZE as i32)
+        .collect::<Vec<_>>();
+
+    let response = request().method("GET").path("/books").reply(&api).await;
+    assert_eq!(response.status(), 200);
+    assert!(response.headers().get("content-length").is_none());
+    let books: Vec<models::Book> = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(books.iter().map(|book| book.id).collect::<Vec<_>>(), ids);
+
+    let response = request()
+        .method("GET")
+        .path("/v2/books?fields=id,authors")
+        .header("accept", "application/x-ndjson")
+        .reply(&api)
+        .await;
+    assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+    let lines = std::str::from_utf8(response.body())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), ids.len());
+    assert_eq!(lines[0], json!({"id": 1, "authors": ["Test Author"]}));
+    assert_eq!(lines[ids.len() - 1]["id"], count);
+
+    let response = request()
+        .method("GET")
+        .path("/books?fields=title,id")
+        .header("accept", "text/csv")
+        .reply(&api)
+        .await;
+    let csv = std::str::from_utf8(response.body()).unwrap();
+    let rows = csv.split_terminator("\r\n").collect::<Vec<_>>();
+    assert_eq!(rows.len(), ids.len() + 1);
+    assert_eq!(rows[0], "id,title");
+    assert_eq!(rows[ids.len()], format!("{0},Book {0}", count));
+}
+
+#[tokio::test]
+async fn test_bulk_import_reports_each_row() {
+    use crate::import::BATCH_SIZE;
+    use crate::models::{ImportReport, ImportStatus};
+
+    let db_pool = setup_test_db();
+    let api = filters::books(db_pool.clone(), &Default::default())
+        .or(warp::path("v2").and(filters::v2::books(db_pool, &Default::default())))
+        .recover(errors::handle_rejection);
+    let import = |path: &str, content_type: &str, body: &str| {
+        request()
+            .method("POST")
+            .path(path)
+            .header("content-type", content_type)
+            .body(body)
+    };
+
+    let csv = "title,author,date_published,cover_image\r\n\
+               \"Commas, Quotes \"\"\",Ann,2024-01-01,\r\n\
+               ,No Title,2024-01-01,\r\n\
+               \r\n\
+               Too Short,Bob\r\n\
+               Last,Cy,2024-01-01,http://example.com/cover.jpg\r\n";
+    let response = import("/books/import?dry_run=true", "text/csv", csv)
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let report: ImportReport = serde_json::from_slice(response.body()).unwrap();
+    assert!(report.dry_run);
+    assert_eq!((report.valid, report.created, report.failed), (2, 0, 2));
+    let statuses = report.rows.iter().map(|row| row.status).collect::<Vec<_>>();
+    assert_eq!(
+        statuses,
+        [
+            ImportStatus::Valid,
+            ImportStatus::Failed,
+            ImportStatus::Failed,
+            ImportStatus::Valid
+        ]
+    );
+    assert_eq!(
+        report.rows[1].reason.as_deref(),
+        Some("title must not be empty")
+    );
+    let response = request().method("GET").path("/books").reply(&api).await;
+    assert_eq!(response.body().as_ref(), b"[]");
+
+    let response = import("/books/import", "text/csv", csv).reply(&api).await;
+    let report: ImportReport = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!((report.valid, report.created, report.failed), (2, 2, 2));
+    let id = report.rows[0].id.unwrap();
+    let response = request()
+        .method("GET")
+        .path(&format!("/books/{}", id))
+        .reply(&api)
+        .await;
+    let book: models::Book = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(book.title, "Commas, Quotes \"");
+    assert_eq!(report.rows[3].id, Some(id + 1));
+
+    let lines = (0..BATCH_SIZE * 2 + 1)
+        .map(|i| {
+            json!({
+                "title": format!("Book {}", i),
+                "authors": ["Ann", "Bob"],
+                "date_published": "2024-01-01",
+                "cover_image": ""
+            })
+            .to_string()
+        })
+        .chain([
+            json!({"title": "No authors", "authors": [], "date_published": "", "cover_image": ""})
+                .to_string(),
+            "not json".to_string(),
+        ])
+        .collect::<Vec<_>>()
+        .join("\n");
+    let response = import("/v2/books/import", "application/x-ndjson", &lines)
+        .reply(&api)
+        .await;
+    let report: ImportReport = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(report.created, BATCH_SIZE * 2 + 1);
+    assert_eq!(report.failed, 2);
+    assert_eq!(
+        report.rows[BATCH_SIZE * 2 + 1].reason.as_deref(),
+        Some("authors must name at least one author")
+    );
+    let response = request()
+        .method("GET")
+        .path(&format!("/v2/books/{}", report.rows[0].id.unwrap()))
+        .reply(&api)
+        .await;
+    let book: models::v2::Book = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(book.authors, ["Ann", "Bob"]);
+
+    let response = import("/books/import", "application/json", "{}")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 415);
+    let response = import("/books/import", "text/csv", "title,title\r\nA,B\r\n")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+    let response = import("/books/import?dry_run=maybe", "text/csv", csv)
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+    let response = request()
+        .method("GET")
+        .path("/books/import")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 405);
+    assert_eq!(response.headers()["allow"], "POST, OPTIONS");
+}
+
+#[tokio::test]
+async fn test_export_downloads_books_as_files() {
+    let db_pool = setup_test_db();
+    let api = filters::books(db_pool.clone(), &Default::default())
+        .or(warp::path("v2").and(filters::v2::books(db_pool, &Default::default())))
+        .recover(errors::handle_rejection);
+
+    // An empty export still has its CSV header.
+    let response = request()
+        .path("/books/export?format=csv&fields=title,id")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.body().as_ref(), b"id,title\r\n");
+
+    for title in ["First", "Second, with a comma"] {
+        let response = request()
+            .method("POST")
+            .path("/v2/books")
+            .json(&json!({
+                "title": title,
+                "authors": ["Ann", "Bob"],
+                "date_published": "2024-01-01",
+                "cover_image": ""
+            }))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 201);
+    }
+
+    let response = request()
+        .path("/v2/books/export?format=csv")
+        .reply(&api)
+        .await;
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/csv; charset=utf-8"
+    );
+    let disposition = response.headers()["content-disposition"].to_str().unwrap();
+    assert!(
+        disposition.starts_with("attachment; filename=\"books-") && disposition.ends_with(".csv\""),
+        "{}",
+        disposition
+    );
+    assert!(!response.headers().contains_key("vary"));
+    let rows = std::str::from_utf8(response.body())
+        .unwrap()
+        .split_terminator("\r\n")
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rows,
+        [
+            "id,title,authors,date_published,cover_image",
+            "1,First,Ann; Bob,2024-01-01,",
+            "2,\"Second, with a comma\",Ann; Bob,2024-01-01,"
+        ]
+    );
+
+    let response = request()
+        .path("/books/export?format=ndjson&fields=author")
+        .reply(&api)
+        .await;
+    assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+    assert_eq!(
+        response.body().as_ref(),
+        b"{\"author\":\"Ann; Bob\"}\n{\"author\":\"Ann; Bob\"}\n"
+    );
+
+    let response = request().path("/v2/books/export").reply(&api).await;
+    assert!(response.headers()["content-disposition"]
+        .to_str()
+        .unwrap()
+        .ends_with(".json\""));
+    let books: Vec<models::v2::Book> = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(books.len(), 2);
+
+    for path in ["/books/export?format=csv", "/v2/books/export?format=csv"] {
+        let response = request().method("HEAD").path(path).reply(&api).await;
+        assert_eq!(response.status(), 200, "{}", path);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/csv; charset=utf-8"
+        );
+        assert!(response.headers()["content-disposition"]
+            .to_str()
+            .unwrap()
+            .ends_with(".csv\""));
+        assert!(response.body().is_empty());
+    }
+
+    for path in ["/books/export?format=xml", "/books/export?fields=isbn"] {
+        let response = request().path(path).reply(&api).await;
+        assert_eq!(response.status(), 400, "{}", path);
+        let response = request().method("HEAD").path(path).reply(&api).await;
+        assert_eq!(response.status(), 400, "{}", path);
+    }
+    let response = request()
+        .method("POST")
+        .path("/books/export")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 405);
+    assert_eq!(response.headers()["allow"], "GET, HEAD, OPTIONS");
+}
+
+/// Streams a million rows and reports resident memory as the body arrives.
+/// Run with `cargo test --release -- --ignored --nocapture bench_`.
+#[tokio::test]
+#[ignore]
+async fn bench_streaming_a_million_books_keeps_rss_flat() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const ROWS: usize = 1_000_000;
+    let rss_kib = || {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))
+            .and_then(|rest| rest.trim().trim_end_matches(" kB").parse::<u64>().ok())
+            .unwrap()
+    };
+
+    let db_pool = setup_test_db();
+    {
+        let conn = &mut db_pool.get().unwrap();
+        for batch in 0..ROWS / 1000 {
+            let rows = (0..1000)
+                .map(|i| models::NewBook {
+                    title: format!("Book {}", batch * 1000 + i),
+                    author: "Test Author".to_string(),
+                    date_published: "2024-01-01".to_string(),
+                    cover_image: "http://example.com/cover.jpg".to_string(),
+                })
+                .collect::<Vec<_>>();
+            db::create_books(conn, &rows).unwrap();
+        }
+    }
+
+    let (addr, server) =
+        warp::serve(filters::books(db_pool, &Default::default()).recover(errors::handle_rejection))
+            .bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /books HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+        .await
+        .unwrap();
+
+    let baseline = rss_kib();
+    let mut peak = baseline;
+    let mut buffer = vec![0; 64 * 1024];
+    let mut received = 0;
+    let mut reported = 0;
+    loop {
+        let read = stream.read(&mut buffer).await.unwrap();
+        if read == 0 {
+            break;
+        }
+        received += read;
+        if received - reported >= 16 << 20 {
+            reported = received;
+            let rss = rss_kib();
+            peak = peak.max(rss);
+            println!("{:>5} MiB received, RSS {:>7} KiB", received >> 20, rss);
+        }
+    }
+
+    println!(
+        "{} MiB in total; RSS {} KiB before, {} KiB at peak",
+        received >> 20,
+        baseline,
+        peak
+    );
+    assert!(received > ROWS * 100);
+    assert!(
+        peak - baseline < 64 * 1024,
+        "RSS grew by {} KiB",
+        peak - baseline
+    );
+}
+
+#[test]
+fn test_format_codecs_round_trip() {
+    let book = json!({
+        "id": 7,
+        "title": "Line\nbreak, \"quotes\" & <tags>",
+        "authors": ["Ann", "Bob"],
+        "date_published": "2024-01-01",
+        "cover_image": ""
+    });
+    let columns = &models::v2::Book::FIELDS;
+
+    let packed = crate::msgpack::to_vec(&json!([book, {"n": -300, "f": 1.5, "none": null}]));
+    assert_eq!(
+        crate::msgpack::from_slice(&packed).unwrap(),
+        json!([book, {"n": -300, "f": 1.5, "none": null}])
+    );
+    assert!(crate::msgpack::from_slice(&packed[..packed.len() - 1]).is_err());
+    assert!(crate::msgpack::from_slice(&[0x91; 64]).is_err());
+
+    // The text formats read every scalar back as a string.
+    let mut read_back = book.clone();
+    read_back["id"] = json!("7");
+
+    let csv = crate::csv::to_string(&book, columns);
+    assert_eq!(crate::csv::from_str(&csv, &["authors"]).unwrap(), read_back);
+    let xml = crate::xml::to_string(&book, "book", columns);
+    assert_eq!(crate::xml::from_str(&xml).unwrap(), read_back);
+    let yaml = crate::yaml::to_string(&book);
+    assert_eq!(crate::yaml::from_str(&yaml).unwrap(), read_back);
+    assert_eq!(
+        crate::yaml::from_str("title: 'It''s' # a comment\nauthors: [Ann, \"Bob, Jr\"]\n").unwrap(),
+        json!({"title": "It's", "authors": ["Ann", "Bob, Jr"]})
+    );
+    assert_eq!(
+        crate::yaml::from_str(
+            "title: 1984\nmeta:\n  pages: 328\nblurb: |\n  Big Brother\n  is watching\n"
+        )
+        .unwrap(),
+        json!({"title": "1984", "meta": {"pages": "328"}, "blurb": "Big Brother\nis watching\n"})
+    );
+    assert_eq!(
+        crate::yaml::from_str("{title: Dune, authors: [Frank Herbert]}").unwrap(),
+        json!({"title": "Dune", "authors": ["Frank Herbert"]})
+    );
+}
+
+#[test]
+fn test_yaml_rendering() {
+    let value = json!({
+        "openapi": "3.0.3",
+        "paths": {"/books/{id}": {"get": {"tags": ["Books"], "parameters": [{"name": "id", "required": true}]}}},
+        "empty": [],
+        "text": "yes: really",
+        "count": 2
+    });
+
+    // Whatever the layout, a YAML reader gets the same document back, with
+    // version strings still strings.
+    let yaml = crate::yaml::to_string(&value);
+    assert_eq!(
+        serde_yaml::from_str::<serde_json::Value>(&yaml).unwrap(),
+        value,
+        "{}",
+        yaml
+    );
+    assert!(
+        yaml.lines().any(|line| line.starts_with("paths:")),
+        "{}",
+        yaml
+    );
+}
+
+#[tokio::test]
+async fn test_compression_codecs_round_trip() {
+    use crate::compression::{Coding, Compression};
+    use std::io::Read;
+    use warp::hyper::Body;
+
+    let text = (0..2000)
+        .map(|i| format!("{{\"id\":{},\"title\":\"Book {}\"}}", i, i * 7 % 13))
+        .collect::<String>();
+    let compression = Arc::new(Compression::new(&config::CompressionConfig::default()));
+    for coding in [Coding::Brotli, Coding::Gzip, Coding::Deflate] {
+        // Many small chunks share one compression history.
+        let chunks = text
+            .as_bytes()
+            .chunks(7)
+            .map(|chunk| Ok::<_, std::convert::Infallible>(chunk.to_vec()))
+            .collect::<Vec<_>>();
+        let api = compression::wrap(
+            Some(compression.clone()),
+            warp::path::end().map(move || {
+                warp::http::Response::builder()
+                    .header("content-type", "application/json")
+                    .body(Body::wrap_stream(futures_util::stream::iter(
+                        chunks.clone(),
+                    )))
+                    .unwrap()
+            }),
+        );
+        let response = request()
+            .header("accept-encoding", coding.name())
+            .reply(&api)
+            .await;
+        assert_eq!(response.headers()["content-encoding"], coding.name());
+        let packed = response.body();
+        assert!(packed.len() < text.len() / 4, "{}", coding.name());
+
+        let mut unpacked = Vec::new();
+        match coding {
+            Coding::Brotli => brotli::Decompressor::new(&packed[..], 4096)
+                .read_to_end(&mut unpacked)
+                .unwrap(),
+            Coding::Gzip => flate2::read::GzDecoder::new(&packed[..])
+                .read_to_end(&mut unpacked)
+                .unwrap(),
+            Coding::Deflate => flate2::read::ZlibDecoder::new(&packed[..])
+                .read_to_end(&mut unpacked)
+                .unwrap(),
+        };
+        assert_eq!(unpacked, text.as_bytes());
+    }
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    std::io::Write::write_all(&mut encoder, text.as_bytes()).unwrap();
+    let packed = encoder.finish().unwrap();
+    let decoded = compression::decode_request(Some("gzip"), &packed, u64::MAX).unwrap();
+    assert_eq!(*decoded, *text.as_bytes());
+    // Concatenated members decode as one.
+    let twice = [packed.clone(), packed.clone()].concat();
+    let decoded = compression::decode_request(Some("x-gzip"), &twice, u64::MAX).unwrap();
+    assert_eq!(decoded.len(), text.len() * 2);
+    let limit = text.len() as u64;
+    assert!(compression::decode_request(Some("gzip"), &packed, limit).is_ok());
+    assert!(matches!(
+        compression::decode_request(Some("gzip"), &packed, limit - 1),
+        Err(crate::errors::Error::PayloadTooLarge)
+    ));
+    assert!(matches!(
+        compression::decode_request(Some("gzip"), &packed[..packed.len() - 1], u64::MAX),
+        Err(crate::errors::Error::InvalidData)
+    ));
+    let mut corrupt = packed.clone();
+    let crc = packed.len() - 8;
+    corrupt[crc] ^= 1;
+    assert!(compression::decode_request(Some("gzip"), &corrupt, u64::MAX).is_err());
+    assert!(matches!(
+        compression::decode_request(Some("br"), &packed, u64::MAX),
+        Err(crate::errors::Error::UnsupportedMediaType)
+    ));
+}
+
+#[tokio::test]
+async fn test_responses_are_compressed_per_accept_encoding() {
+    use crate::compression::{Coding, Compression};
+
+    assert_eq!(
+        Coding::from_accept_encoding("gzip, deflate, br"),
+        Some(Coding::Brotli)
+    );
+    assert_eq!(
+        Coding::from_accept_encoding("br;q=0.5, x-gzip"),
+        Some(Coding::Gzip)
+    );
+    assert_eq!(
+        Coding::from_accept_encoding("*;q=0.1, br;q=0"),
+        Some(Coding::Gzip)
+    );
+    assert_eq!(Coding::from_accept_encoding("identity"), None);
+
+    let repo = Arc::new(InMemoryBookRepository::new());
+    let api = compression::wrap(
+        Some(Arc::new(Compression::new(
+            &config::CompressionConfig::default(),
+        ))),
+        filters::books(repo, &Default::default()).recover(errors::handle_rejection),
+    );
+    for i in 0..40 {
+        let response = request()
+            .method("POST")
+            .path("/books")
+            .header("accept-encoding", "gzip")
+            .json(&json!({
+                "title": format!("Book {}", i),
+                "author": "Author",
+                "date_published": "2024-01-01",
+                "cover_image": ""
+            }))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 201);
+        // Single books are below the size threshold.
+        assert!(!response.headers().contains_key("content-encoding"));
+        assert_eq!(response.headers()["vary"], "accept-encoding");
+    }
+
+    let plain = request().path("/books").reply(&api).await;
+    assert!(!plain.headers().contains_key("content-encoding"));
+
+    // Streamed lists are compressed chunk by chunk.
+    let response = request()
+        .path("/books")
+        .header("accept-encoding", "gzip;q=0.5, deflate")
+        .reply(&api)
+        .await;
+    assert_eq!(response.headers()["content-encoding"], "deflate");
+    assert!(!response.headers().contains_key("content-length"));
+    let mut inflated = Vec::new();
+    std::io::Read::read_to_end(
+        &mut flate2::read::ZlibDecoder::new(&response.body()[..]),
+        &mut inflated,
+    )
+    .unwrap();
+    assert_eq!(inflated, *plain.body());
+
+    let response = request()
+        .path("/books")
+        .header("accept-encoding", "gzip")
+        .header("accept", "application/yaml")
+        .reply(&api)
+        .await;
+    assert_eq!(response.headers()["content-encoding"], "gzip");
+    assert_eq!(
+        response.headers()["content-length"],
+        response.body().len().to_string()
+    );
+    let vary = response
+        .headers()
+        .get_all("vary")
+        .iter()
+        .collect::<Vec<_>>();
+    assert_eq!(vary, ["accept", "accept-encoding"]);
+    let yaml = request()
+        .path("/books")
+        .header("accept", "application/yaml")
+        .reply(&api)
+        .await;
+    let mut inflated = Vec::new();
+    std::io::Read::read_to_end(
+        &mut flate2::read::GzDecoder::new(&response.body()[..]),
+        &mut inflated,
+    )
+    .unwrap();
+    assert_eq!(inflated, *yaml.body());
+
+    let response = request()
+        .path("/books")
+        .header("accept-encoding", "br")
+        .reply(&api)
+        .await;
+    assert_eq!(response.headers()["content-encoding"], "br");
+
+    // MessagePack is already compact.
+    let response = request()
+        .path("/books")
+        .header("accept-encoding", "gzip")
+        .header("accept", "application/msgpack")
+        .reply(&api)
+        .await;
+    assert!(!response.headers().contains_key("content-encoding"));
+}
+
+#[tokio::test]
+async fn test_gzip_request_bodies() {
+    let api = filters::books(Arc::new(InMemoryBookRepository::new()), &Default::default())
+        .recover(errors::handle_rejection);
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    std::io::Write::write_all(
+        &mut encoder,
+        br#"{"title": "Zipped", "author": "Author", "date_published": "2024-01-01", "cover_image": ""}"#,
+    )
+    .unwrap();
+    let mut body = encoder.finish().unwrap();
+
+    let response = request()
+        .method("POST")
+        .path("/books")
+        .header("content-type", "application/json")
+        .header("content-encoding", "gzip")
+        .body(body.clone())
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 201);
+    let book: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(book["title"], "Zipped");
+
+    let response = request()
+        .method("POST")
+        .path("/books")
+        .header("content-type", "application/json")
+        .header("content-encoding", "compress")
+        .body(body.clone())
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 415);
+
+    // The route's limit applies to the inflated body.
+    let limits = config::LimitsConfig {
+        max_body_bytes: 64,
+        ..Default::default()
+    };
+    let small = filters::books(Arc::new(InMemoryBookRepository::new()), &limits)
+        .recover(errors::handle_rejection);
+    let response = request()
+        .method("POST")
+        .path("/books")
+        .header("content-type", "application/json")
+        .header("content-encoding", "gzip")
+        .body(body.clone())
+        .reply(&small)
+        .await;
+    assert_eq!(response.status(), 413);
+
+    body.truncate(body.len() - 4);
+    let response = request()
+        .method("POST")
+        .path("/books")
+        .header("content-type", "application/json")
+        .header("content-encoding", "gzip")
+        .body(body)
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+}
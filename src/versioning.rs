@@ -0,0 +1,74 @@
+use time::format_description::FormatItem;
+use time::macros::{datetime, format_description};
+use time::OffsetDateTime;
+use warp::http::header::{self, HeaderValue};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+/// When `/v1` and the unversioned paths were superseded by `/v2`.
+pub const V1_DEPRECATED: OffsetDateTime = datetime!(2026-10-18 00:00 UTC);
+
+/// After this date `/v1` and the unversioned paths may be removed.
+pub const V1_SUNSET: OffsetDateTime = datetime!(2027-04-30 00:00 UTC);
+
+/// An IMF-fixdate, e.g. `Fri, 30 Apr 2027 00:00:00 GMT`.
+const HTTP_DATE: &[FormatItem<'static>] = format_description!(
+    "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT"
+);
+
+/// Whether `path` belongs to `/v1` or to its unversioned aliases.
+pub fn is_deprecated(path: &str) -> bool {
+    path == "/v1" || path.starts_with("/v1/") || is_book_path(path)
+}
+
+/// The `/v2` path replacing a deprecated book path.
+pub fn successor(path: &str) -> Option<String> {
+    let path = path.strip_prefix("/v1").unwrap_or(path);
+    is_book_path(path).then(|| format!("/v2{}", path))
+}
+
+fn is_book_path(path: &str) -> bool {
+    path == "/books" || path.starts_with("/books/")
+}
+
+/// Marks responses from deprecated versions with `Deprecation` (RFC 9745)
+/// and `Sunset` (RFC 8594) headers, linking to the `/v2` successor.
+pub fn wrap<F, T>(filter: F) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (T,), Error = Rejection> + Clone + Send + Sync + 'static,
+    T: Reply,
+{
+    warp::path::full()
+        .and(filter)
+        .map(|path: FullPath, reply: T| {
+            let mut response = reply.into_response();
+            if !is_deprecated(path.as_str()) {
+                return response;
+            }
+
+            let headers = response.headers_mut();
+            headers.insert(
+                "deprecation",
+                format!("@{}", V1_DEPRECATED.unix_timestamp())
+                    .parse()
+                    .expect("timestamps are valid headers"),
+            );
+            headers.insert(
+                "sunset",
+                V1_SUNSET
+                    .format(HTTP_DATE)
+                    .expect("HTTP dates format")
+                    .parse()
+                    .expect("HTTP dates are valid headers"),
+            );
+            if let Some(successor) = successor(path.as_str()) {
+                if let Ok(link) =
+                    HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", successor))
+                {
+                    headers.insert(header::LINK, link);
+                }
+            }
+            response
+        })
+}
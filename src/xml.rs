@@ -0,0 +1,177 @@
+use quick_xml::events::{BytesDecl, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use serde_json::{Map, Value};
+use std::io;
+
+/// Deepest nesting [`from_str`] accepts: the record, a field and a list item.
+const MAX_DEPTH: usize = 3;
+
+/// Writes a record as `<{element}>` with a child per field, in `columns`
+/// order, or an array of records inside `<{element}s>`.
+///
+/// Array fields get a child per item, named after the field without its
+/// trailing `s`, e.g. `<authors><author>…</author></authors>`.
+pub fn to_string(value: &Value, element: &str, columns: &[&str]) -> String {
+    let mut writer = Writer::new(Vec::new());
+    let written: io::Result<()> = (|| {
+        writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+        writer.get_mut().push(b'\n');
+        match value {
+            Value::Array(records) => {
+                writer
+                    .create_element(format!("{}s", element))
+                    .write_inner_content(|writer| {
+                        records
+                            .iter()
+                            .try_for_each(|record| write_record(writer, record, element, columns))
+                    })?;
+            }
+            record => write_record(&mut writer, record, element, columns)?,
+        }
+        writer.get_mut().push(b'\n');
+        Ok(())
+    })();
+    written.expect("writing to a Vec cannot fail");
+    String::from_utf8(writer.into_inner()).expect("XML is written as UTF-8")
+}
+
+fn write_record(
+    writer: &mut Writer<Vec<u8>>,
+    record: &Value,
+    element: &str,
+    columns: &[&str],
+) -> io::Result<()> {
+    writer
+        .create_element(element)
+        .write_inner_content(|writer| {
+            columns
+                .iter()
+                .try_for_each(|column| match record.get(*column) {
+                    Some(value) => write_element(writer, column, value),
+                    None => Ok(()),
+                })
+        })?;
+    Ok(())
+}
+
+fn write_element(writer: &mut Writer<Vec<u8>>, name: &str, value: &Value) -> io::Result<()> {
+    let element = writer.create_element(name);
+    match value {
+        Value::Null => element.write_empty()?,
+        Value::Array(items) => {
+            let item = name.strip_suffix('s').unwrap_or("item");
+            element.write_inner_content(|writer| {
+                items
+                    .iter()
+                    .try_for_each(|value| write_element(writer, item, value))
+            })?
+        }
+        Value::Object(map) => element.write_inner_content(|writer| {
+            map.iter()
+                .try_for_each(|(key, value)| write_element(writer, key, value))
+        })?,
+        Value::String(s) => element.write_text_content(BytesText::new(s))?,
+        value => element.write_text_content(BytesText::new(&value.to_string()))?,
+    };
+    Ok(())
+}
+
+/// Reads a single record element into an object of strings; a field with
+/// child elements becomes an array of their text.
+///
+/// Document types are rejected, so entity expansion cannot be abused.
+pub fn from_str(input: &str) -> Result<Value, String> {
+    let mut reader = Reader::from_str(input);
+    let mut record = Map::new();
+    let mut open = Vec::new();
+    let mut has_root = false;
+
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(_) | Event::Empty(_) if open.is_empty() && has_root => {
+                return Err("content after the root element".to_string());
+            }
+            Event::Start(_) | Event::Empty(_) if open.len() == MAX_DEPTH => {
+                return Err("nested too deeply".to_string());
+            }
+            Event::Start(tag) => {
+                has_root = true;
+                open.push(Element::new(tag.name().as_ref())?);
+            }
+            Event::Empty(tag) => {
+                has_root = true;
+                open.push(Element::new(tag.name().as_ref())?);
+                close(&mut open, &mut record)?;
+            }
+            Event::End(_) => close(&mut open, &mut record)?,
+            Event::Text(text) => {
+                let text = text.unescape().map_err(|e| e.to_string())?;
+                match open.last_mut() {
+                    Some(element) => element.text.push_str(&text),
+                    None if text.trim().is_empty() => {}
+                    None => return Err("text outside the root element".to_string()),
+                }
+            }
+            Event::CData(text) => {
+                let text = std::str::from_utf8(&text).map_err(|e| e.to_string())?;
+                match open.last_mut() {
+                    Some(element) => element.text.push_str(text),
+                    None => return Err("text outside the root element".to_string()),
+                }
+            }
+            Event::DocType(_) => return Err("document types are not supported".to_string()),
+            Event::Eof if !open.is_empty() => return Err("unclosed element".to_string()),
+            Event::Eof if !has_root => return Err("expected an element".to_string()),
+            Event::Eof => return Ok(Value::Object(record)),
+            // The declaration, comments and processing instructions.
+            _ => {}
+        }
+    }
+}
+
+struct Element {
+    name: String,
+    text: String,
+    items: Vec<Value>,
+}
+
+impl Element {
+    fn new(name: &[u8]) -> Result<Element, String> {
+        Ok(Element {
+            name: String::from_utf8(name.to_vec())
+                .map_err(|_| "element names must be UTF-8".to_string())?,
+            text: String::new(),
+            items: Vec::new(),
+        })
+    }
+}
+
+/// Ends the innermost open element: a field of `record`, or an item of the
+/// field around it.
+fn close(open: &mut Vec<Element>, record: &mut Map<String, Value>) -> Result<(), String> {
+    let element = open.pop().ok_or("unexpected end tag")?;
+    if !element.items.is_empty() && !element.text.trim().is_empty() {
+        return Err(format!("mixed content in {}", element.name));
+    }
+    let value = if element.items.is_empty() {
+        Value::String(element.text)
+    } else {
+        Value::Array(element.items)
+    };
+    match open.len() {
+        // The record's own content is its fields.
+        0 => {}
+        1 => {
+            if record.contains_key(&element.name) {
+                return Err(format!("duplicate element {}", element.name));
+            }
+            record.insert(element.name, value);
+        }
+        _ => open
+            .last_mut()
+            .expect("an element is open")
+            .items
+            .push(value),
+    }
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use serde_json::Value;
+
+/// Renders `value` as block-style YAML, for serving the OpenAPI document as `/openapi.yaml`.
+pub fn to_string(value: &Value) -> String {
+    serde_yaml::to_string(value).expect("JSON values render as YAML")
+}
+
+/// Reads a single YAML document.
+///
+/// Numbers and booleans are read back as strings, since every book field is
+/// text; `null` and `~` stay null.
+pub fn from_str(input: &str) -> Result<Value, String> {
+    serde_yaml::from_str(input)
+        .map(stringify_scalars)
+        .map_err(|e| e.to_string())
+}
+
+fn stringify_scalars(value: Value) -> Value {
+    match value {
+        Value::Number(n) => Value::String(n.to_string()),
+        Value::Bool(b) => Value::String(b.to_string()),
+        Value::Array(items) => Value::Array(items.into_iter().map(stringify_scalars).collect()),
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, stringify_scalars(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}